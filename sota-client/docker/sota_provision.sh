@@ -148,13 +148,14 @@ pkey_file = "$cert_dir/$out_dev.pem"
 director_server = "$SOTA_GATEWAY_URI/director"
 repo_server = "$SOTA_GATEWAY_URI/repo"
 primary_ecu_serial = "$primary_serial"
-primary_ecu_hardware_identifier = "$hardware_id"
+hardware_id = "$hardware_id"
 metadata_path = "$cert_dir/metadata"
 private_key_path = "$cert_dir/$primary_serial.der"
 public_key_path = "$cert_dir/$primary_serial.pub"
 
 [[ecus]]
 ecu_serial = "$primary_serial"
+hardware_id = "$hardware_id"
 public_key_path = "$cert_dir/$primary_serial.pub"
 manifest_path = "$cert_dir/$primary_serial.manifest"
 EOF
@@ -164,6 +165,7 @@ EOF
 
 [[ecus]]
 ecu_serial = "$serial"
+hardware_id = "$hw_id"
 public_key_path = "$cert_dir/$serial.pub"
 manifest_path = "$cert_dir/$serial.manifest"
 EOF
@@ -176,10 +178,16 @@ generate_manifests() {
 
 generate_installers() {
   while read -r serial hw_id; do
+    mkdir -p "metadata/$serial"
+    cp "metadata/director/root.json" "metadata/$serial/root.json"
     cat > "$serial.toml" <<EOF
 serial = "$serial"
+hardware_id = "$hw_id"
 private_key_path = "$cert_dir/$serial.der"
 signature_type = "rsassa-pss"
+metadata_path = "$cert_dir/metadata/$serial"
+primary_serial = "$primary_serial"
+primary_public_key_path = "$cert_dir/$primary_serial.pub"
 EOF
   done < "$in_ecus"
 }
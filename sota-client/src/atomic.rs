@@ -2,33 +2,62 @@ use bincode::{self, Infinite};
 use bytes::Bytes;
 use byteorder::{BigEndian, ByteOrder};
 use chrono::{DateTime, Utc};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::curve25519::{curve25519, curve25519_base};
+use crypto::hkdf::{hkdf_expand, hkdf_extract};
+use crypto::sha2::Sha256;
+use hex::ToHex;
 use json;
 use libc;
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
-use std::io::{BufReader, ErrorKind, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
 use std::path::Path;
 use std::str;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use datatype::{Error, Manifests, TufSigned, Util};
-use images::{ImageReader, ImageWriter};
-
+use datatype::{Error, Key, Manifests, PrivateKey, RoleMeta, Signature, SignatureType, TufSigned, Util};
+use images::{CHUNK_SIZE, ImageReader, ImageWriter};
+use transport::{Listener, Transport};
+use uptane::Verifier;
+
+
+/// Time allowed for each read or write of a handshake, which includes signing.
+const HANDSHAKE_TIMEOUT_MS: u64 = 5000;
+/// Handshake messages are read before a peer is authenticated so are kept small.
+const HANDSHAKE_MAX_BYTES: u32 = 64 * 1024;
+/// Room for the message fields and Poly1305 tag around an image chunk.
+const FRAME_OVERHEAD: u32 = 4 * 1024;
+/// Largest encrypted frame from a `Secondary`, which only sends acks, signed
+/// reports and chunk requests.
+const PRIMARY_MAX_BYTES: u32 = CHUNK_SIZE as u32 + FRAME_OVERHEAD;
+/// Largest encrypted frame from the `Primary`, which sends image chunks as well
+/// as the Director metadata to verify.
+const SECONDARY_MAX_BYTES: u32 = 16 * 1024 * 1024;
+/// Length of the Poly1305 tag appended to each encrypted frame.
+const TAG_LEN: usize = 16;
+/// Maximum number of image chunks a `Secondary` will request at once.
+const CHUNK_WINDOW: usize = 16;
+/// Time the `Primary` waits for a message before requesting the current state again.
+const REQUEST_INTERVAL_MS: u64 = 100;
 
 lazy_static! {
     static ref VALID_TRANSITIONS: HashMap<State, Vec<State>> = hashmap! {
-        State::Idle   => vec![State::Start],
-        State::Start  => vec![State::Abort, State::Verify],
-        State::Verify => vec![State::Abort, State::Fetch],
-        State::Fetch  => vec![State::Abort, State::Commit],
-        State::Commit => vec![State::Abort],
-        State::Abort  => vec![],
+        State::Idle     => vec![State::Start],
+        State::Start    => vec![State::Abort, State::Verify],
+        State::Verify   => vec![State::Abort, State::Fetch],
+        State::Fetch    => vec![State::Abort, State::Commit],
+        State::Commit   => vec![State::Abort, State::Rollback],
+        State::Abort    => vec![],
+        State::Rollback => vec![],
     };
 }
 
@@ -42,13 +71,12 @@ pub enum State {
     Fetch,
     Commit,
     Abort,
+    Rollback,
 }
 
 /// A message to be picked up by a `Primary`.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum PrimaryMessage {
-    /// New TCP Connection.
-    Connect { serial: String },
     /// Acknowledgement of a state transition.
     Ack { txid: Uuid, state: State, payload: Option<Payload> },
     /// Request for an image chunk.
@@ -73,6 +101,7 @@ pub type Payloads = HashMap<String, HashMap<State, Payload>>;
 /// Data that may be delivered to a `Secondary` before executing state transition.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Payload {
+    FetchFailed(Bytes),
     Generic(Bytes),
     ImageMeta(Bytes),
     OstreePackage(Bytes),
@@ -84,6 +113,9 @@ pub enum Payload {
 /// The interface for transitioning a `Secondary` to the next state.
 pub trait Step: Send {
     fn step(&mut self, state: State, payload: Option<Payload>) -> Result<Option<StepData>, Error>;
+
+    /// Called when received data fails verification so it can be reported.
+    fn attack_detected(&mut self, _attack: String) {}
 }
 
 /// Data that may be returned following a state transition.
@@ -110,14 +142,14 @@ pub struct Primary<'s> {
     signed:  HashMap<String, TufSigned>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    server: Option<&'s TcpServer>,
+    server: Option<&'s BusServer>,
 }
 
 impl<'s> Primary<'s> {
     /// Create a new `Primary` that will coordinate the transactional secondaries.
     pub fn new(payloads: Payloads,
                images:   HashMap<String, ImageReader>,
-               server:   &'s TcpServer,
+               server:   &'s BusServer,
                timeout:  Duration,
                recover:  Option<String>) -> Self {
         Primary {
@@ -129,11 +161,12 @@ impl<'s> Primary<'s> {
             server:   Some(server),
 
             acks: hashmap! {
-                State::Start  => HashSet::new(),
-                State::Verify => HashSet::new(),
-                State::Fetch  => HashSet::new(),
-                State::Commit => HashSet::new(),
-                State::Abort  => HashSet::new(),
+                State::Start    => HashSet::new(),
+                State::Verify   => HashSet::new(),
+                State::Fetch    => HashSet::new(),
+                State::Commit   => HashSet::new(),
+                State::Abort    => HashSet::new(),
+                State::Rollback => HashSet::new(),
             },
             started: Utc::now(),
             timeout: timeout,
@@ -143,7 +176,7 @@ impl<'s> Primary<'s> {
     }
 
     /// Recover from a crash by requesting an update on missing `Secondary` acks.
-    pub fn recover<P: AsRef<Path>>(path: P, server: &'s TcpServer) -> Result<Self, Error> {
+    pub fn recover<P: AsRef<Path>>(path: P, server: &'s BusServer) -> Result<Self, Error> {
         let mut primary: Primary = json::from_reader(BufReader::new(File::open(&path)?))?;
         info!("Primary state recovered from `{}`", path.as_ref().display());
         primary.server = Some(server);
@@ -152,17 +185,30 @@ impl<'s> Primary<'s> {
         Ok(primary)
     }
 
-    /// Wake each `Secondary` then execute the three-phase commit process.
+    /// Wake each `Secondary` then execute the three-phase commit process,
+    /// resuming from the current state of a recovered transaction. When any
+    /// `Secondary` fails to commit, the others are told to roll back.
     pub fn commit(&mut self) -> Result<(), Error> {
-        self.transition(State::Start)?;
-        self.transition(State::Verify)?;
-        self.transition(State::Fetch)?;
-        self.transition(State::Commit)?;
-        info!("Transaction {} complete.", self.txid);
+        let outcome = self.transition(State::Start)
+            .and_then(|_| self.transition(State::Verify))
+            .and_then(|_| self.transition(State::Fetch))
+            .and_then(|_| self.transition(State::Commit))
+            .and_then(|_| match self.state {
+                State::Abort | State::Rollback => { let state = self.state; self.transition(state) }
+                _ => Ok(())
+            });
+        match outcome {
+            Ok(()) => info!("Transaction {} complete.", self.txid),
+            Err(Error::AtomicAbort(_)) | Err(Error::AtomicTimeout) => (),
+            Err(_) => return outcome
+        }
         if let Some(ref path) = self.recover { let _ = fs::remove_file(path); }
+        outcome?;
 
         if ! self.aborted().is_empty() {
             Err(Error::AtomicAbort(format!("Secondary aborts: {:?}", self.aborted())))
+        } else if ! self.rolled_back().is_empty() {
+            Err(Error::AtomicAbort(format!("Secondary rollbacks: {:?}", self.rolled_back())))
         } else if self.committed().len() < self.payloads.len() {
             Err(Error::AtomicTimeout)
         } else {
@@ -170,6 +216,19 @@ impl<'s> Primary<'s> {
         }
     }
 
+    /// Wake each `Secondary` then abort the transaction before anything is
+    /// fetched, such as when an image for one of them couldn't be downloaded.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        let outcome = self.transition(State::Start).and_then(|_| self.transition(State::Abort));
+        match outcome {
+            Ok(()) => info!("Transaction {} aborted.", self.txid),
+            Err(Error::AtomicAbort(_)) | Err(Error::AtomicTimeout) => (),
+            Err(_) => return outcome
+        }
+        if let Some(ref path) = self.recover { let _ = fs::remove_file(path); }
+        outcome
+    }
+
     /// A list of the acknowledged `Secondary` commits.
     pub fn committed(&self) -> &HashSet<String> {
         self.acks.get(&State::Commit).expect("commit acks")
@@ -180,6 +239,16 @@ impl<'s> Primary<'s> {
         self.acks.get(&State::Abort).expect("abort acks")
     }
 
+    /// A list of the acknowledged `Secondary` rollbacks.
+    pub fn rolled_back(&self) -> &HashSet<String> {
+        self.acks.get(&State::Rollback).expect("rollback acks")
+    }
+
+    /// The payload to be delivered to a `Secondary` at a specific state.
+    pub fn payload(&self, serial: &str, state: State) -> Option<&Payload> {
+        self.payloads.get(serial).and_then(|states| states.get(&state))
+    }
+
     /// Convert the completed transaction into a list of signed ECU reports.
     pub fn into_manifests(self) -> Manifests {
         self.payloads
@@ -192,18 +261,19 @@ impl<'s> Primary<'s> {
             .collect()
     }
 
-    /// Transition all secondaries to the next state.
+    /// Transition all secondaries to the next state, or collect the missing
+    /// acks of the current state after recovering from a crash.
     fn transition(&mut self, state: State) -> Result<(), Error> {
-        if ! is_valid(self.state, state) { return Ok(()) }
-        info!("Transaction {} moving to {:?}.", self.txid, state);
-        self.checkpoint(state)?;
+        if self.state != state {
+            if ! is_valid(self.state, state) { return Ok(()) }
+            info!("Transaction {} moving to {:?}.", self.txid, state);
+            self.checkpoint(state)?;
+        }
         self.send_request(state)?;
 
-        while self.state == state && self.acks(state).len() < self.payloads.len() {
+        while self.state == state && ! self.is_acked(state) {
             match self.read_message() {
                 Some((serial, msg)) => match msg {
-                    PrimaryMessage::Connect { serial } => Ok(trace!("connect: {}", serial)),
-
                     PrimaryMessage::Ack { txid, state, payload } => {
                         if txid != self.txid { continue }
                         debug!("ACK from {}: {:?}", serial, state);
@@ -214,6 +284,9 @@ impl<'s> Primary<'s> {
                         if state == State::Abort && in_progress(self.state) {
                             self.transition(State::Abort)?;
                             Err(Error::AtomicAbort(serial))
+                        } else if state == State::Abort && self.state == State::Commit {
+                            self.transition(State::Rollback)?;
+                            Err(Error::AtomicAbort(serial))
                         } else {
                             Ok(())
                         }
@@ -232,10 +305,10 @@ impl<'s> Primary<'s> {
 
                 None => {
                     if self.is_timeout() {
+                        if self.state == State::Commit { self.transition(State::Rollback)?; }
                         Err(Error::AtomicTimeout)
                     } else {
-                        self.send_request(self.state)?;
-                        Ok(thread::sleep(Duration::from_millis(100)))
+                        self.send_request(self.state)
                     }
                 }
             }?;
@@ -254,16 +327,21 @@ impl<'s> Primary<'s> {
         Ok(())
     }
 
-    /// Request each transactional `Secondary` move to the next `State`.
+    /// Request each transactional `Secondary` move to the next `State`. A
+    /// rollback is sent with the payload that was fetched.
     fn send_request(&self, state: State) -> Result<(), Error> {
         for (serial, states) in &self.payloads {
             if state == State::Start {
                 self.write_message(serial, &SecondaryMessage::Start { txid: self.txid })?;
-            } else if self.acks(state).get(serial).is_none() {
+            } else if ! self.is_done(serial, state) {
+                let payload = match state {
+                    State::Rollback => states.get(&State::Fetch),
+                    _ => states.get(&state)
+                };
                 self.write_message(serial, &SecondaryMessage::Step {
                     txid: self.txid,
                     state: state,
-                    payload: states.get(&state).cloned()
+                    payload: payload.cloned()
                 })?;
             }
         }
@@ -274,16 +352,27 @@ impl<'s> Primary<'s> {
         self.acks.get(&state).expect("acks")
     }
 
+    /// Whether every `Secondary` is done with the `State`.
+    fn is_acked(&self, state: State) -> bool {
+        self.payloads.keys().all(|serial| self.is_done(serial, state))
+    }
+
+    /// Whether a `Secondary` is done with the `State`, where one that aborted
+    /// has nothing to roll back.
+    fn is_done(&self, serial: &str, state: State) -> bool {
+        self.acks(state).contains(serial) || (state == State::Rollback && self.aborted().contains(serial))
+    }
+
     fn is_timeout(&self) -> bool {
         Utc::now().signed_duration_since(self.started).to_std().expect("duration") > self.timeout
     }
 
     fn read_message(&mut self) -> Option<(String, PrimaryMessage)> {
-        self.server.as_mut().expect("tcp server").read_message()
+        self.server.as_mut().expect("bus server").read_message(Duration::from_millis(REQUEST_INTERVAL_MS))
     }
 
     fn write_message(&self, serial: &str, msg: &SecondaryMessage) -> Result<(), Error> {
-        match self.server.as_ref().expect("tcp server").write_message(serial, msg) {
+        match self.server.as_ref().expect("bus server").write_message(serial, msg) {
             Ok(()) => Ok(()),
             Err(ref err) if should_retry(err) => Ok(()),
             Err(err) => Err(err)
@@ -306,16 +395,19 @@ pub struct Secondary {
     payload: Option<Payload>,
     writers: HashMap<String, ImageWriter>,
     report:  Option<TufSigned>,
+    finished: Option<Finished>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    client: Option<TcpClient>,
+    window: usize,
+    #[serde(skip_serializing, skip_deserializing)]
+    client: Option<BusClient>,
     #[serde(skip_serializing, skip_deserializing)]
     step: Option<Box<Step>>,
 }
 
 impl Secondary {
     /// Create a `Secondary` that listens on the bus for state transitions messages.
-    pub fn new(client: TcpClient, step: Box<Step>, timeout: Duration, recover: Option<String>) -> Self {
+    pub fn new(client: BusClient, step: Box<Step>, timeout: Duration, recover: Option<String>) -> Self {
         Secondary {
             txid:   None,
             serial: client.serial.clone(),
@@ -328,64 +420,81 @@ impl Secondary {
             payload: None,
             writers: HashMap::new(),
             report:  None,
+            finished: None,
 
+            window: CHUNK_WINDOW,
             client: Some(client),
             step: Some(step),
         }
     }
 
-    /// Recover from a crash while a transaction was in progress.
-    pub fn recover<P: AsRef<Path>>(path: P, client: TcpClient, step: Box<Step>) -> Result<Self, Error> {
+    /// Recover from a crash while a transaction was in progress. A completed
+    /// transaction is remembered so its outcome can be acknowledged again.
+    pub fn recover<P: AsRef<Path>>(path: P, client: BusClient, step: Box<Step>) -> Result<Self, Error> {
         let mut follower: Secondary = json::from_reader(BufReader::new(File::open(&path)?))?;
         info!("Secondary `{}` state recovered from `{}`", follower.serial, path.as_ref().display());
+        follower.window = CHUNK_WINDOW;
         follower.client = Some(client);
         follower.step = Some(step);
         follower.started = Utc::now();
+        follower.finish();
         Ok(follower)
     }
 
+    /// Recover from the `recover` path if a checkpoint exists there, otherwise
+    /// create a new `Secondary` that will checkpoint to it.
+    pub fn resume(client: BusClient, step: Box<Step>, timeout: Duration, recover: Option<String>) -> Result<Self, Error> {
+        match recover {
+            Some(ref path) if Path::new(path).exists() => Secondary::recover(path, client, step),
+            _ => Ok(Secondary::new(client, step, timeout, recover))
+        }
+    }
+
     /// Block until a new transaction is started then read new messages until we
-    /// reach a terminating state or time-out.
+    /// reach a terminating state or time-out. Listening again after a commit
+    /// allows the `Primary` to request a rollback.
     pub fn listen(&mut self) -> Result<(), Error> {
         info!("Starting a Secondary ECU listener for serial `{}`", self.serial);
+        self.finish();
 
         while ! is_terminal(self.state) {
             self.read_message()
                 .and_then(|msg| match msg {
                     SecondaryMessage::Start { txid } => {
+                        if self.is_finished(txid) { return Ok(()) }
+                        match self.txid {
+                            Some(current) if current != txid => {
+                                warn!("{} abandoning transaction {} for {}", self.serial, current, txid);
+                                self.reset();
+                            }
+                            _ => ()
+                        }
                         self.txid = Some(txid);
                         self.transition(State::Start, None)
                     }
 
                     SecondaryMessage::Step { txid, state, payload } => {
-                        if txid != self.txid() { return Ok(()) }
+                        if self.is_finished(txid) { return self.step_finished(state, payload) }
+                        if Some(txid) != self.txid { return Ok(()) }
                         self.transition(state, payload)
                     }
 
                     SecondaryMessage::Chunk { txid, image, index, chunk } => {
-                        if txid != self.txid() { return Ok(()) }
-                        let next_index = {
+                        if Some(txid) != self.txid { return Ok(()) }
+                        {
                             let writer = self.writers.get_mut(&image)
                                 .ok_or_else(|| Error::Image(format!("writer not found: {}", image)))?;
+                            if ! writer.chunks_available.contains(&index) { return Ok(()) }
                             writer.write_direct(&chunk, index)?;
-                            if let Some(index) = writer.next_chunk() {
-                                Some(index)
-                            } else {
-                                writer.verify_direct()?;
-                                None
-                            }
-                        };
-                        match next_index {
-                            Some(index) => self.request_chunk(image, index),
-                            None => {
-                                self.state = self.next;
-                                self.write_ack()
-                            }
                         }
-                   }
+                        self.request_chunks(&image)
+                    }
                 })
                 .or_else(|err| {
-                    if ! should_retry(&err) {
+                    if is_closed(&err) {
+                        debug!("{} disconnected: {}", self.serial, err);
+                        Err(err)
+                    } else if ! should_retry(&err) {
                         debug!("{} moving to abort: {}", self.serial, err);
                         let _ = self.transition(State::Abort, None);
                         Err(err)
@@ -394,12 +503,12 @@ impl Secondary {
                         let _ = self.transition(State::Abort, None);
                         Err(Error::AtomicTimeout)
                     } else {
-                        Ok(thread::sleep(Duration::from_millis(100)))
+                        self.resend_chunks()
                     }
                 })?;
         }
 
-        if let Some(ref path) = self.recover { fs::remove_file(path)?; }
+        self.persist()?;
         if self.state == State::Abort {
             Err(Error::AtomicAbort(self.serial.clone()))
         } else {
@@ -425,13 +534,13 @@ impl Secondary {
                 }
 
                 Some(StepData::ImageWriter(writer)) => {
-                    if self.writers.get(&writer.meta.image_name).is_none() {
-                        let image = writer.meta.image_name.clone();
+                    let image = writer.meta.image_name.clone();
+                    if self.writers.get(&image).is_none() {
                         let _ = self.writers.insert(image.clone(), writer);
-                        self.request_chunk(image, 0)
                     } else {
-                        Ok(trace!("skipping existing writer: {}", writer.meta.image_name))
+                        trace!("resuming existing writer: {}", image);
                     }
+                    self.request_chunks(&image)
                 }
 
                 Some(StepData::TufReport(report)) => {
@@ -452,16 +561,82 @@ impl Secondary {
         self.started = Utc::now();
         self.next = state;
         self.payload = payload;
+        self.persist()
+    }
+
+    /// Remember the outcome of a completed transaction then forget it.
+    fn finish(&mut self) {
+        if let (Some(txid), true) = (self.txid, is_terminal(self.state)) {
+            self.finished = Some(Finished { txid: txid, state: self.state, report: self.report.take() });
+            self.reset();
+        }
+    }
+
+    /// Forget any transaction in progress.
+    fn reset(&mut self) {
+        self.txid = None;
+        self.state = State::Idle;
+        self.next = State::Idle;
+        self.payload = None;
+        self.writers.clear();
+        self.report = None;
+    }
+
+    /// Write the current state to the recovery path.
+    fn persist(&self) -> Result<(), Error> {
         if let Some(ref path) = self.recover {
             Util::write_file(path, &json::to_vec(self)?)?;
         }
         Ok(())
     }
 
-    /// Send a request to the `Primary` for a new image chunk.
-    fn request_chunk(&mut self, image: String, index: u64) -> Result<(), Error> {
-        let txid = self.txid();
-        self.write_message(&PrimaryMessage::Chunk { txid, image, index })
+    /// Keep a window of image chunk requests outstanding with the `Primary`,
+    /// or move to the next state once every chunk is written and verified.
+    fn request_chunks(&mut self, image: &str) -> Result<(), Error> {
+        let next = {
+            let writer = self.writers.get_mut(image)
+                .ok_or_else(|| Error::Image(format!("writer not found: {}", image)))?;
+            if ! writer.chunks_available.is_empty() {
+                Some(writer.next_chunks(self.window))
+            } else if let Err(err) = writer.verify_direct() {
+                let attack = format!("image {} failed verification: {}", image, err);
+                self.step.as_mut().expect("step").attack_detected(attack);
+                return Err(err);
+            } else {
+                None
+            }
+        };
+
+        match next {
+            Some(indices) => {
+                let txid = self.txid();
+                for index in indices {
+                    self.write_message(&PrimaryMessage::Chunk { txid: txid, image: image.into(), index: index })?;
+                }
+                Ok(())
+            }
+            None => {
+                self.state = self.next;
+                self.write_ack()
+            }
+        }
+    }
+
+    /// Request any outstanding image chunks again in case they were lost.
+    fn resend_chunks(&mut self) -> Result<(), Error> {
+        if self.state == self.next { return Ok(()) }
+        let mut images = Vec::new();
+        for (image, writer) in &mut self.writers {
+            if ! writer.chunks_requested.is_empty() {
+                writer.chunks_requested.clear();
+                images.push(image.clone());
+            }
+        }
+        for image in images {
+            trace!("requesting {} chunks again", image);
+            self.request_chunks(&image)?;
+        }
+        Ok(())
     }
 
     /// Send an acknowledgement to the `Primary` of a state transition.
@@ -476,6 +651,38 @@ impl Secondary {
         self.write_message(&PrimaryMessage::Ack { txid, state, payload })
     }
 
+    /// Acknowledge the outcome of a completed transaction again.
+    fn write_finished(&mut self) -> Result<(), Error> {
+        let msg = {
+            let finished = self.finished.as_ref().expect("finished");
+            let payload = match finished.report {
+                Some(ref report) => Some(Payload::SignedReport(Bytes::from(json::to_vec(&report)?))),
+                None => None
+            };
+            PrimaryMessage::Ack { txid: finished.txid, state: finished.state, payload: payload }
+        };
+        self.write_message(&msg)
+    }
+
+    /// Roll back a committed transaction, or acknowledge its outcome again.
+    fn step_finished(&mut self, state: State, payload: Option<Payload>) -> Result<(), Error> {
+        let committed = self.finished.as_ref().map_or(false, |finished| finished.state == State::Commit);
+        if state != State::Rollback || ! committed {
+            return self.write_finished();
+        }
+
+        let finished = self.finished.take().expect("finished");
+        self.txid = Some(finished.txid);
+        self.state = State::Commit;
+        self.next = State::Commit;
+        self.report = finished.report;
+        self.transition(state, payload)
+    }
+
+    fn is_finished(&self, txid: Uuid) -> bool {
+        self.finished.as_ref().map_or(false, |finished| finished.txid == txid)
+    }
+
     fn step(&mut self, state: State, payload: Option<Payload>) -> Result<Option<StepData>, Error> {
         self.step.as_mut().expect("step").step(state, payload)
     }
@@ -489,11 +696,11 @@ impl Secondary {
     }
 
     fn read_message(&mut self) -> Result<SecondaryMessage, Error> {
-        self.client.as_mut().expect("tcp client").read_message()
+        self.client.as_mut().expect("bus client").read_message()
     }
 
     fn write_message(&mut self, msg: &PrimaryMessage) -> Result<(), Error> {
-        match self.client.as_mut().expect("tcp server").write_message(msg) {
+        match self.client.as_mut().expect("bus client").write_message(msg) {
             Ok(()) => Ok(()),
             Err(ref err) if should_retry(err) => Ok(()),
             Err(err) => Err(err)
@@ -501,13 +708,21 @@ impl Secondary {
     }
 }
 
+/// The outcome of the last transaction completed by a `Secondary`.
+#[derive(Serialize, Deserialize)]
+struct Finished {
+    txid:   Uuid,
+    state:  State,
+    report: Option<TufSigned>,
+}
+
 fn is_valid(from: State, to: State) -> bool {
     VALID_TRANSITIONS.get(&from).expect("transitions").contains(&to)
 }
 
 fn is_terminal(state: State) -> bool {
     match state {
-        State::Commit | State::Abort => true,
+        State::Commit | State::Abort | State::Rollback => true,
         _ => false
     }
 }
@@ -515,7 +730,20 @@ fn is_terminal(state: State) -> bool {
 fn in_progress(state: State) -> bool {
     match state {
         State::Start | State::Verify | State::Fetch => true,
-        State::Idle  | State::Commit | State::Abort => false
+        State::Idle  | State::Commit | State::Abort | State::Rollback => false
+    }
+}
+
+/// Whether the other end of the connection has gone away.
+fn is_closed(err: &Error) -> bool {
+    match *err {
+        Error::Io(ref err) => match err.kind() {
+            ErrorKind::BrokenPipe      |
+            ErrorKind::ConnectionReset |
+            ErrorKind::UnexpectedEof   => true,
+            _ => false
+        },
+        _ => false
     }
 }
 
@@ -539,90 +767,323 @@ fn should_retry(err: &Error) -> bool {
 }
 
 
-/// A `TcpServer` will read `PrimaryMessage`s from each connected `TcpClient`.
-pub struct TcpServer {
-    clients:  Arc<Mutex<HashMap<String, TcpStream>>>,
-    messages: Arc<Mutex<VecDeque<(String, PrimaryMessage)>>>,
-    _addr:    SocketAddr,
+/// The keys that authenticate one end of the Primary/Secondary bus: its own
+/// serial and private key, plus the public keys of the peers it will accept.
+#[derive(Clone)]
+pub struct BusKeys {
+    pub serial:      String,
+    pub private_key: PrivateKey,
+    pub sig_type:    SignatureType,
+    pub peer_keys:   HashMap<String, Key>,
 }
 
-impl Default for TcpServer {
-    fn default() -> Self {
-        TcpServer::new("127.0.0.1:0").expect("bind local")
+impl BusKeys {
+    /// Sign this end's view of the handshake.
+    fn sign(&self, transcript: &Transcript) -> Result<Vec<Signature>, Error> {
+        Ok(self.private_key.sign_data(json::to_value(transcript)?, self.sig_type)?.signatures)
+    }
+
+    /// Verify a peer's signatures over the handshake with the key configured for its serial.
+    fn verify(&self, serial: &str, transcript: &Transcript, signatures: Vec<Signature>) -> Result<(), Error> {
+        let key = self.peer_keys.get(serial).ok_or_else(|| Error::AtomicHandshake(format!("unknown serial {}", serial)))?;
+        let id = key.key_id()?;
+        let mut verifier = Verifier::default();
+        verifier.add_key(id.clone(), key.clone())?;
+        let signed = TufSigned { signatures: signatures, signed: json::to_value(transcript)? };
+        verifier.verify_signatures(&RoleMeta { keyids: hashset!{ id }, threshold: 1, version: 0 }, &signed)
+            .map_err(|err| Error::AtomicHandshake(format!("{} not authenticated: {}", serial, err)))
     }
 }
 
-impl TcpServer {
-    /// Start a `TcpServer` and accept new connections in a background thread.
-    pub fn new<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
-        let listener = TcpListener::bind(&addr)?;
-        let server = TcpServer {
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            messages: Arc::new(Mutex::new(VecDeque::new())),
-            _addr: listener.local_addr()?,
-        };
+/// Plaintext messages exchanged before a session is encrypted.
+#[derive(Serialize, Deserialize, Debug)]
+enum Handshake {
+    /// A secondary's serial and ephemeral public key.
+    Hello { serial: String, ephemeral: Vec<u8> },
+    /// The primary's serial, ephemeral public key and signed transcript.
+    Accept { serial: String, ephemeral: Vec<u8>, signatures: Vec<Signature> },
+    /// The secondary's signed transcript.
+    Finish { signatures: Vec<Signature> },
+}
 
-        let clients = Arc::clone(&server.clients);
-        let messages = Arc::clone(&server.messages);
-        thread::spawn(move || {
-            for stream in listener.incoming() {
-                stream.map_err(Error::Io)
-                    .and_then(|s| Self::accept_stream(s, Arc::clone(&clients), Arc::clone(&messages)))
-                    .unwrap_or_else(|err| warn!("Unable to open TCP connection: {}", err))
-            }
-        });
+/// The data signed by each end of a handshake, binding both serials to both ephemeral keys.
+#[derive(Serialize)]
+struct Transcript<'a> {
+    signer:        &'a str,
+    primary:       &'a str,
+    secondary:     &'a str,
+    primary_key:   String,
+    secondary_key: String,
+}
+
+impl<'a> Transcript<'a> {
+    fn new(signer: &'a str, primary: &'a str, secondary: &'a str, primary_key: &[u8], secondary_key: &[u8]) -> Self {
+        Transcript {
+            signer:        signer,
+            primary:       primary,
+            secondary:     secondary,
+            primary_key:   primary_key.to_hex(),
+            secondary_key: secondary_key.to_hex(),
+        }
+    }
+}
+
+/// One direction of an encrypted session, using a counter as the nonce of each frame.
+struct Cipher {
+    key:     [u8; 32],
+    counter: u64,
+    frames:  FrameReader,
+}
 
-        Ok(server)
-    }
-
-    /// Accept a new TCP connection and push incoming messages into a queue.
-    fn accept_stream(
-        mut stream: TcpStream,
-        clients: Arc<Mutex<HashMap<String, TcpStream>>>,
-        messages: Arc<Mutex<VecDeque<(String, PrimaryMessage)>>>,
-    ) -> Result<(), Error> {
-        stream.set_read_timeout(Some(Duration::from_millis(500)))?;
-        stream.set_write_timeout(Some(Duration::from_millis(500)))?;
-
-        let msg = read_stream(&mut stream)?;
-        if let PrimaryMessage::Connect { serial: s } = msg {
-            debug!("serial {} connected", s);
-            let client_stream = stream.try_clone()?;
-            clients.lock().unwrap().insert(s.clone(), client_stream);
-
-            let messages = Arc::clone(&messages);
-            thread::spawn(move || loop {
-                match read_stream(&mut stream) {
-                    Ok(msg) => messages.lock().unwrap().push_back((s.clone(), msg)),
-                    Err(ref err) if should_retry(err) => thread::sleep(Duration::from_millis(500)),
-                    Err(err) => warn!("Unable to read message from {}: {}", s, err)
+impl Cipher {
+    /// Derive the ciphers used to write to the primary and to the secondary.
+    fn derive(shared: &[u8], primary_key: &[u8], secondary_key: &[u8]) -> (Cipher, Cipher) {
+        let mut salt = primary_key.to_vec();
+        salt.extend_from_slice(secondary_key);
+        let mut prk = [0; 32];
+        hkdf_extract(Sha256::new(), &salt, shared, &mut prk);
+
+        let mut to_primary = Cipher { key: [0; 32], counter: 0, frames: FrameReader::new(PRIMARY_MAX_BYTES) };
+        let mut to_secondary = Cipher { key: [0; 32], counter: 0, frames: FrameReader::new(SECONDARY_MAX_BYTES) };
+        hkdf_expand(Sha256::new(), &prk, b"sota secondary to primary", &mut to_primary.key);
+        hkdf_expand(Sha256::new(), &prk, b"sota primary to secondary", &mut to_secondary.key);
+        (to_primary, to_secondary)
+    }
+
+    /// Encrypt and write a message to the stream.
+    fn write<T: Serialize>(&mut self, stream: &mut Write, data: &T) -> Result<(), Error> {
+        let sealed = self.seal(data)?;
+        write_frame(stream, &sealed)
+    }
+
+    /// Read and decrypt the next message from the stream.
+    fn read<T: DeserializeOwned>(&mut self, stream: &mut Read) -> Result<T, Error> {
+        let sealed = self.frames.read(stream)?;
+        self.open(&sealed)
+    }
+
+    fn seal<T: Serialize>(&mut self, data: &T) -> Result<Vec<u8>, Error> {
+        let plain = bincode::serialize(data, Infinite)?;
+        let nonce = self.nonce();
+        let mut sealed = vec![0; plain.len() + TAG_LEN];
+        {
+            let (out, tag) = sealed.split_at_mut(plain.len());
+            ChaCha20Poly1305::new(&self.key, &nonce, &[]).encrypt(&plain, out, tag);
+        }
+        Ok(sealed)
+    }
+
+    fn open<T: DeserializeOwned>(&mut self, sealed: &[u8]) -> Result<T, Error> {
+        if sealed.len() < TAG_LEN { return Err(Error::AtomicDecrypt) }
+        let (data, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+        let nonce = self.nonce();
+        let mut plain = vec![0; data.len()];
+        if ChaCha20Poly1305::new(&self.key, &nonce, &[]).decrypt(data, &mut plain, tag) {
+            Ok(bincode::deserialize(&plain)?)
+        } else {
+            Err(Error::AtomicDecrypt)
+        }
+    }
+
+    fn nonce(&mut self) -> [u8; 8] {
+        let mut nonce = [0; 8];
+        BigEndian::write_u64(&mut nonce, self.counter);
+        self.counter += 1;
+        nonce
+    }
+}
+
+/// Generate an ephemeral X25519 key pair for a handshake.
+fn ephemeral() -> Result<([u8; 32], [u8; 32]), Error> {
+    let mut secret = [0; 32];
+    SystemRandom::new().fill(&mut secret)?;
+    let public = curve25519_base(&secret);
+    Ok((secret, public))
+}
+
+/// Calculate the shared secret with a peer's ephemeral public key.
+fn shared_secret(secret: &[u8], public: &[u8]) -> Result<[u8; 32], Error> {
+    if public.len() != 32 {
+        return Err(Error::AtomicHandshake(format!("{} byte ephemeral key", public.len())));
+    }
+    let shared = curve25519(secret, public);
+    if shared.iter().all(|byte| *byte == 0) {
+        Err(Error::AtomicHandshake("low order ephemeral key".into()))
+    } else {
+        Ok(shared)
+    }
+}
+
+
+/// A `BusServer` will read `PrimaryMessage`s from each authenticated `BusClient`.
+pub struct BusServer {
+    keys:    BusKeys,
+    peers:   Arc<Mutex<HashMap<String, Peer>>>,
+    inbox:   Arc<Inbox>,
+    next_id: Arc<AtomicUsize>,
+}
+
+/// The writing half of an authenticated connection.
+struct Peer {
+    id:     usize,
+    writer: Box<Transport>,
+    cipher: Cipher,
+}
+
+/// The queue of messages read from every peer, waking a waiting reader as
+/// each one arrives.
+struct Inbox {
+    messages: Mutex<VecDeque<(String, PrimaryMessage)>>,
+    arrived:  Condvar,
+}
+
+impl Inbox {
+    fn push(&self, serial: String, msg: PrimaryMessage) {
+        self.messages.lock().unwrap().push_back((serial, msg));
+        self.arrived.notify_all();
+    }
+
+    /// Pop the next message, waiting up to `timeout` for one to arrive.
+    fn pop(&self, timeout: Duration) -> Option<(String, PrimaryMessage)> {
+        let deadline = Instant::now() + timeout;
+        let mut messages = self.messages.lock().unwrap();
+        while messages.is_empty() {
+            let now = Instant::now();
+            if now >= deadline { return None }
+            messages = self.arrived.wait_timeout(messages, deadline - now).unwrap().0;
+        }
+        messages.pop_front()
+    }
+}
+
+impl BusServer {
+    /// Create a `BusServer` that will accept the peers listed in the `BusKeys`.
+    pub fn new(keys: BusKeys) -> Self {
+        BusServer {
+            keys:    keys,
+            peers:   Arc::new(Mutex::new(HashMap::new())),
+            inbox:   Arc::new(Inbox { messages: Mutex::new(VecDeque::new()), arrived: Condvar::new() }),
+            next_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Authenticate new connections from the `Listener` in background threads.
+    pub fn listen<L: Listener + 'static>(&self, mut listener: L) {
+        let keys = self.keys.clone();
+        let peers = Arc::clone(&self.peers);
+        let inbox = Arc::clone(&self.inbox);
+        let next_id = Arc::clone(&self.next_id);
+        thread::spawn(move || loop {
+            let transport = match listener.accept() {
+                Ok(transport) => transport,
+                Err(Error::Recv(_)) => break,
+                Err(err) => { warn!("Unable to open bus connection: {}", err); continue }
+            };
+            let keys = keys.clone();
+            let peers = Arc::clone(&peers);
+            let inbox = Arc::clone(&inbox);
+            let id = next_id.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || {
+                match Self::accept_peer(id, transport, &keys, &peers) {
+                    Ok((serial, transport, reader)) => Self::read_peer(id, serial, transport, reader, &peers, &inbox),
+                    Err(err) => warn!("Rejecting bus connection: {}", err)
                 }
             });
-        } else {
-            error!("Rejecting TCP connect message: {:?}", msg);
+        });
+    }
+
+    /// Authenticate a new connection and register it for writing.
+    fn accept_peer(
+        id: usize,
+        mut transport: Box<Transport>,
+        keys: &BusKeys,
+        peers: &Mutex<HashMap<String, Peer>>,
+    ) -> Result<(String, Box<Transport>, Cipher), Error> {
+        transport.set_timeout(Duration::from_millis(HANDSHAKE_TIMEOUT_MS))?;
+
+        let (serial, secondary_key) = match read_handshake(&mut transport)? {
+            Handshake::Hello { serial, ephemeral } => (serial, ephemeral),
+            msg => return Err(Error::AtomicHandshake(format!("expected Hello, got {:?}", msg)))
+        };
+        if ! keys.peer_keys.contains_key(&serial) {
+            return Err(Error::AtomicHandshake(format!("unknown serial {}", serial)));
+        }
+        let (secret, public) = ephemeral()?;
+        let shared = shared_secret(&secret, &secondary_key)?;
+        let signatures = keys.sign(&Transcript::new("primary", &keys.serial, &serial, &public, &secondary_key))?;
+        write_handshake(&mut transport, &Handshake::Accept { serial: keys.serial.clone(), ephemeral: public.to_vec(), signatures: signatures })?;
+
+        match read_handshake(&mut transport)? {
+            Handshake::Finish { signatures } => {
+                let transcript = Transcript::new("secondary", &keys.serial, &serial, &public, &secondary_key);
+                keys.verify(&serial, &transcript, signatures)?;
+            }
+            msg => return Err(Error::AtomicHandshake(format!("expected Finish, got {:?}", msg)))
         }
 
-        Ok(())
+        transport.set_timeout(Duration::from_millis(500))?;
+        let (reader, writer) = Cipher::derive(&shared, &public, &secondary_key);
+        debug!("serial {} connected", serial);
+        let peer = Peer { id: id, writer: transport.try_clone()?, cipher: writer };
+        peers.lock().unwrap().insert(serial.clone(), peer);
+        Ok((serial, transport, reader))
+    }
+
+    /// Push incoming messages from an authenticated connection into a queue.
+    fn read_peer(
+        id: usize,
+        serial: String,
+        mut transport: Box<Transport>,
+        mut reader: Cipher,
+        peers: &Mutex<HashMap<String, Peer>>,
+        inbox: &Inbox,
+    ) {
+        loop {
+            match reader.read(&mut transport) {
+                Ok(msg) => inbox.push(serial.clone(), msg),
+                Err(ref err) if is_closed(err) => {
+                    debug!("{} disconnected", serial);
+                    break;
+                }
+                Err(ref err) if should_retry(err) => {
+                    if peers.lock().unwrap().get(&serial).map_or(true, |peer| peer.id != id) {
+                        debug!("{} reconnected", serial);
+                        break;
+                    }
+                }
+                Err(err @ Error::AtomicDecrypt) |
+                Err(err @ Error::AtomicPayload) => {
+                    error!("Disconnecting {}: {}", serial, err);
+                    break;
+                }
+                Err(err) => warn!("Unable to read message from {}: {}", serial, err)
+            }
+        }
+
+        let mut peers = peers.lock().unwrap();
+        if peers.get(&serial).map_or(false, |peer| peer.id == id) {
+            let _ = peers.remove(&serial);
+        }
     }
 
-    /// Read the next `PrimaryMessage`.
-    pub fn read_message(&self) -> Option<(String, PrimaryMessage)> {
-        self.messages.lock().unwrap().pop_front()
+    /// Read the next `PrimaryMessage`, waiting up to `timeout` for one to arrive.
+    pub fn read_message(&self, timeout: Duration) -> Option<(String, PrimaryMessage)> {
+        self.inbox.pop(timeout)
     }
 
     /// Write a `SecondaryMessage` to a specific serial.
     pub fn write_message(&self, serial: &str, msg: &SecondaryMessage) -> Result<(), Error> {
-        let mut clients = self.clients.lock().unwrap();
+        let mut peers = self.peers.lock().unwrap();
         let outcome = {
-            let mut stream = clients.get(serial).ok_or_else(|| Error::AtomicOffline(serial.into()))?;
+            let peer = peers.get_mut(serial).ok_or_else(|| Error::AtomicOffline(serial.into()))?;
             trace!("writing message to {}: {:?}", serial, msg);
-            write_stream(&mut stream, msg)
+            peer.cipher.write(&mut peer.writer, msg)
         };
         match outcome {
             Ok(()) => Ok(()),
-            Err(Error::Io(ref e)) if e.kind() == ErrorKind::BrokenPipe => {
+            Err(ref err) if is_closed(err) => {
                 trace!("{} disconnected", serial);
-                let _ = clients.remove(serial);
+                let _ = peers.remove(serial);
                 Err(Error::AtomicOffline(serial.into()))
             },
             Err(err) => Err(err)
@@ -630,25 +1091,38 @@ impl TcpServer {
     }
 }
 
-/// A `TcpClient` will read `SecondaryMessage`s sent from a `TcpServer`.
-pub struct TcpClient {
-    serial: String,
-    stream: TcpStream,
+/// A `BusClient` will read `SecondaryMessage`s sent from a `BusServer`.
+pub struct BusClient {
+    serial:    String,
+    transport: Box<Transport>,
+    reader:    Cipher,
+    writer:    Cipher,
 }
 
-impl TcpClient {
-    /// Connect to the specified `TcpServer`.
-    pub fn new<A: ToSocketAddrs>(serial: String, server: A) -> Result<Self, Error> {
-        let mut stream = TcpStream::connect(server)?;
-        stream.set_read_timeout(Some(Duration::from_millis(500)))?;
-        stream.set_write_timeout(Some(Duration::from_millis(500)))?;
-        write_stream(&mut stream, &PrimaryMessage::Connect { serial: serial.clone() })?;
-        Ok(TcpClient { serial, stream })
+impl BusClient {
+    /// Authenticate each end of a connection to a `BusServer`.
+    pub fn new(keys: &BusKeys, mut transport: Box<Transport>) -> Result<Self, Error> {
+        transport.set_timeout(Duration::from_millis(HANDSHAKE_TIMEOUT_MS))?;
+
+        let (secret, public) = ephemeral()?;
+        write_handshake(&mut transport, &Handshake::Hello { serial: keys.serial.clone(), ephemeral: public.to_vec() })?;
+        let (primary, primary_key, signatures) = match read_handshake(&mut transport)? {
+            Handshake::Accept { serial, ephemeral, signatures } => (serial, ephemeral, signatures),
+            msg => return Err(Error::AtomicHandshake(format!("expected Accept, got {:?}", msg)))
+        };
+        let shared = shared_secret(&secret, &primary_key)?;
+        keys.verify(&primary, &Transcript::new("primary", &primary, &keys.serial, &primary_key, &public), signatures)?;
+        let signatures = keys.sign(&Transcript::new("secondary", &primary, &keys.serial, &primary_key, &public))?;
+        write_handshake(&mut transport, &Handshake::Finish { signatures: signatures })?;
+
+        transport.set_timeout(Duration::from_millis(500))?;
+        let (writer, reader) = Cipher::derive(&shared, &primary_key, &public);
+        Ok(BusClient { serial: keys.serial.clone(), transport: transport, reader: reader, writer: writer })
     }
 
-    /// Read a new message from the connected TCP stream.
+    /// Read a new message from the connected transport.
     pub fn read_message(&mut self) -> Result<SecondaryMessage, Error> {
-        match read_stream(&mut self.stream) {
+        match self.reader.read(&mut self.transport) {
             Ok(msg) => {
                 trace!("{} got message: {:?}", self.serial, msg);
                 Ok(msg)
@@ -660,46 +1134,77 @@ impl TcpClient {
     /// Write a new `PrimaryMessage`.
     pub fn write_message(&mut self, msg: &PrimaryMessage) -> Result<(), Error> {
         trace!("{} writing message: {:?}", self.serial, msg);
-        write_stream(&mut self.stream, msg)
+        self.writer.write(&mut self.transport, msg)
     }
 }
 
-/// Read the data size then read the rest of the data from the stream.
-fn read_stream<T: DeserializeOwned>(stream: &mut Read) -> Result<T, Error> {
-    let mut size_buf = [0; 4];
-    stream.read_exact(&mut size_buf)?;
-    let num_bytes = BigEndian::read_u32(&size_buf);
+/// Read a plaintext handshake message from the stream.
+fn read_handshake(stream: &mut Read) -> Result<Handshake, Error> {
+    Ok(bincode::deserialize(&FrameReader::new(HANDSHAKE_MAX_BYTES).read(stream)?)?)
+}
 
-    let mut data_buf = vec![0; num_bytes as usize];
-    let mut bytes_read = 0;
-    loop {
-        match stream.read(&mut data_buf[bytes_read..]) {
-            Ok(0) => return Ok(bincode::deserialize(&data_buf)?),
-            Ok(n) => bytes_read += n,
-            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => continue,
-            Err(err) => return Err(err.into())
-        }
-    }
+/// Write a plaintext handshake message to the stream.
+fn write_handshake(stream: &mut Write, msg: &Handshake) -> Result<(), Error> {
+    write_frame(stream, &bincode::serialize(msg, Infinite)?)
 }
 
-/// Write the data size then write the referenced data to the stream.
-fn write_stream<T: Serialize>(stream: &mut Write, data: &T) -> Result<(), Error> {
-    let encoded = bincode::serialize(data, Infinite)?;
-    let mut size_buf = [0; 4];
-    BigEndian::write_u32(&mut size_buf, encoded.len() as u32);
-    loop {
-        match stream.write_all(&size_buf) {
-            Ok(()) => break,
-            Err(err) => match err.kind() {
-                ErrorKind::TimedOut | ErrorKind::WouldBlock => continue,
-                _ => return Err(err.into())
+/// Reads length-prefixed frames, keeping any partially read frame across read
+/// timeouts so that the next read resumes where the last one stopped.
+struct FrameReader {
+    max_bytes: u32,
+    buf:       Vec<u8>,
+}
+
+impl FrameReader {
+    fn new(max_bytes: u32) -> Self {
+        FrameReader { max_bytes: max_bytes, buf: Vec::new() }
+    }
+
+    /// Read the data size then read the rest of the data from the stream.
+    fn read(&mut self, stream: &mut Read) -> Result<Vec<u8>, Error> {
+        loop {
+            if self.buf.len() < 4 {
+                self.fill(stream, 4)?;
+                continue;
+            }
+
+            let num_bytes = BigEndian::read_u32(&self.buf[..4]);
+            if num_bytes > self.max_bytes { return Err(Error::AtomicPayload) }
+            let total = 4 + num_bytes as usize;
+            if self.buf.len() < total {
+                self.fill(stream, total)?;
+            } else {
+                let data = self.buf.split_off(4);
+                self.buf.clear();
+                return Ok(data);
             }
         }
     }
 
+    /// Read towards `total` buffered bytes, keeping what was read on error.
+    fn fill(&mut self, stream: &mut Read, total: usize) -> Result<(), Error> {
+        let start = self.buf.len();
+        self.buf.resize(total, 0);
+        let result = stream.read(&mut self.buf[start..]);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.buf.truncate(start + read);
+        match result {
+            Ok(0) => Err(io::Error::new(ErrorKind::UnexpectedEof, "frame incomplete").into()),
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.into())
+        }
+    }
+}
+
+/// Write the data size followed by the referenced data to the stream.
+fn write_frame(stream: &mut Write, data: &[u8]) -> Result<(), Error> {
+    let mut frame = vec![0; 4];
+    BigEndian::write_u32(&mut frame, data.len() as u32);
+    frame.extend_from_slice(data);
+
     let mut written = 0;
     loop {
-        match stream.write(&encoded[written..]) {
+        match stream.write(&frame[written..]) {
             Ok(0) => return Ok(stream.flush()?),
             Ok(n) => written += n,
             Err(err) => match err.kind() {
@@ -715,11 +1220,13 @@ fn write_stream<T: Serialize>(stream: &mut Write, data: &T) -> Result<(), Error>
 mod tests {
     use super::*;
     use base64;
-    use ring::rand::{SecureRandom, SystemRandom};
+    use crypto::ed25519;
     use std::{panic, thread};
+    use std::sync::mpsc::{self, Sender};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::net::{UnixListener, UnixStream};
     use time;
-
-    use datatype::{PrivateKey, SignatureType};
+    use transport::{channel, ChannelConnector};
 
 
     lazy_static! {
@@ -832,6 +1339,14 @@ mod tests {
         }
     }
 
+    struct CommitSlow;
+    impl Step for CommitSlow {
+        fn step(&mut self, state: State, _: Option<Payload>) -> Result<Option<StepData>, Error> {
+            if state == State::Commit { thread::sleep(Duration::from_secs(3)) }
+            Ok(step_data(state))
+        }
+    }
+
     struct VerifyCrash;
     impl Step for VerifyCrash {
         fn step(&mut self, state: State, _: Option<Payload>) -> Result<Option<StepData>, Error> {
@@ -873,14 +1388,22 @@ mod tests {
 
     fn connect(prefix: &str) -> (
         Payloads,
-        TcpServer,
-        TcpClient,
-        TcpClient,
-        TcpClient,
+        BusServer,
+        BusClient,
+        BusClient,
+        BusClient,
         String,
         String,
         String,
     ) {
+        let (payloads, srv, connector, a, b, c) = serve(prefix);
+        let ca = client(&a, &connector);
+        let cb = client(&b, &connector);
+        let cc = client(&c, &connector);
+        (payloads, srv, ca, cb, cc, a, b, c)
+    }
+
+    fn serve(prefix: &str) -> (Payloads, BusServer, ChannelConnector, String, String, String) {
         let now = time::precise_time_ns().to_string();
         let a = format!("{}_{}_a", prefix, now);
         let b = format!("{}_{}_b", prefix, now);
@@ -891,12 +1414,167 @@ mod tests {
             c.clone() => hashmap!{},
         };
 
-        let srv = TcpServer::default();
-        let ca = TcpClient::new(a.clone(), &srv._addr).expect("ca");
-        let cb = TcpClient::new(b.clone(), &srv._addr).expect("cb");
-        let cc = TcpClient::new(c.clone(), &srv._addr).expect("cc");
+        let srv = BusServer::new(primary_keys(1, &[&a, &b, &c]));
+        let (listener, connector) = channel();
+        srv.listen(listener);
+        (payloads, srv, connector, a, b, c)
+    }
 
-        (payloads, srv, ca, cb, cc, a, b, c)
+    fn client(serial: &str, connector: &ChannelConnector) -> BusClient {
+        let transport = connector.connect().expect("connect");
+        BusClient::new(&secondary_keys(serial, 2), Box::new(transport)).expect("client")
+    }
+
+    /// Keep restarting a `Secondary` from its recovery path, as `sota-installer` does.
+    fn keep_listening(serial: String, connector: ChannelConnector) {
+        let path = format!("/tmp/sota-atomic-recover-{}", serial);
+        thread::spawn(move || loop {
+            let client = client(&serial, &connector);
+            let mut secondary = Secondary::resume(client, Box::new(Success), timeout(500), Some(path.clone())).expect("resume");
+            let _ = secondary.listen();
+        });
+    }
+
+    /// Crash the primary after checkpointing `crash` then resume the transaction,
+    /// returning the outcome with the committed and aborted serials.
+    fn primary_crash(prefix: &str, before: &[State], crash: State) -> (Result<(), Error>, HashSet<String>, HashSet<String>, Manifests) {
+        let (payloads, srv, connector, a, b, c) = serve(prefix);
+        for serial in &[a.clone(), b, c] {
+            keep_listening(serial.clone(), connector.clone());
+        }
+
+        let path = format!("/tmp/sota-atomic-recover-{}-primary", a);
+        {
+            let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), Some(path.clone()));
+            for state in before {
+                primary.transition(*state).expect("transition");
+            }
+            primary.checkpoint(crash).expect("checkpoint");
+            primary.send_request(crash).expect("send request");
+        }
+
+        let mut primary = Primary::recover(&path, &srv).expect("recover");
+        let outcome = primary.commit();
+        assert!(! Path::new(&path).exists());
+        let (committed, aborted) = (primary.committed().clone(), primary.aborted().clone());
+        (outcome, committed, aborted, primary.into_manifests())
+    }
+
+    /// Delivers each write after `latency` without blocking the writer, as a
+    /// slow link between ECUs would.
+    struct Laggy {
+        inner: Box<Transport>,
+        tx:    Sender<(Instant, Vec<u8>)>,
+    }
+
+    impl Laggy {
+        fn new(inner: Box<Transport>, latency: Duration) -> Self {
+            let (tx, rx) = mpsc::channel::<(Instant, Vec<u8>)>();
+            let mut out = inner.try_clone().expect("clone transport");
+            thread::spawn(move || for (sent, data) in rx {
+                let now = Instant::now();
+                if sent + latency > now { thread::sleep(sent + latency - now) }
+                if out.write_all(&data).is_err() { break }
+            });
+            Laggy { inner: inner, tx: tx }
+        }
+    }
+
+    impl Transport for Laggy {
+        fn try_clone(&self) -> Result<Box<Transport>, Error> {
+            Ok(Box::new(Laggy { inner: self.inner.try_clone()?, tx: self.tx.clone() }))
+        }
+
+        fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+            self.inner.set_timeout(timeout)
+        }
+    }
+
+    impl Read for Laggy {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(out)
+        }
+    }
+
+    impl Write for Laggy {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.tx.send((Instant::now(), data.to_vec())).map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "link closed"))?;
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Transfer a random image of `size` bytes to one of three secondaries with
+    /// up to `window` chunk requests outstanding, delaying the requests by
+    /// `latency`, and return the time taken by the transaction.
+    fn transfer_image(prefix: &str, size: usize, window: usize, latency: Duration) -> Duration {
+        let mut buf = vec![0; size];
+        SystemRandom::new().fill(&mut buf).expect("fill buf");
+        let image_name = format!("test-image-{}", prefix);
+        let image_dir = format!("/tmp/sota-test-image-{}", time::precise_time_ns());
+        fs::create_dir_all(&image_dir).expect("create dir");
+        Util::write_file(&format!("{}/{}", image_dir, image_name), &buf).expect("write buf");
+        let mut reader = ImageReader::new(image_name.clone(), image_dir).expect("reader");
+        let meta = reader.image_meta().expect("meta");
+
+        let (_, srv, connector, a, b, c) = serve(prefix);
+        let (ca, cb) = (client(&a, &connector), client(&b, &connector));
+        let transport = Laggy::new(Box::new(connector.connect().expect("connect")), latency);
+        let cc = BusClient::new(&secondary_keys(&c, 2), Box::new(transport)).expect("client");
+        let payloads = hashmap!{
+            a.clone() => hashmap!{},
+            b.clone() => hashmap!{},
+            c.clone() => {
+                let bytes = Bytes::from(json::to_vec(&meta).expect("json"));
+                hashmap!{ State::Fetch => Payload::ImageMeta(bytes) }
+            }
+        };
+        let images = hashmap!{image_name => reader};
+
+        let mut primary = Primary::new(payloads, images, &srv, timeout(60000), None);
+        let mut sa = Secondary::new(ca, Box::new(Success), timeout(60000), None);
+        let mut sb = Secondary::new(cb, Box::new(Success), timeout(60000), None);
+        let mut sc = Secondary::new(cc, Box::new(FetchImage), timeout(60000), None);
+        sc.window = window;
+        thread::spawn(move || assert!(sa.listen().is_ok()));
+        thread::spawn(move || assert!(sb.listen().is_ok()));
+        thread::spawn(move || assert!(sc.listen().is_ok()));
+
+        let started = Instant::now();
+        assert!(primary.commit().is_ok());
+        let elapsed = started.elapsed();
+        assert_eq!(primary.committed(), &hashset!{a, b, c});
+        assert_eq!(primary.aborted(), &hashset!{});
+        elapsed
+    }
+
+    fn bus_key(seed: u8) -> (PrivateKey, Key) {
+        let der_key = ed25519::keypair(&[seed; 32]).0.to_vec();
+        let key = SignatureType::Ed25519.public_from_private(&der_key).expect("public key");
+        (PrivateKey::from_der(der_key, SignatureType::Ed25519).expect("private key"), key)
+    }
+
+    /// Keys for a primary signing with `seed` that accepts secondaries signing with seed 2.
+    fn primary_keys(seed: u8, serials: &[&String]) -> BusKeys {
+        BusKeys {
+            serial:      "test-primary".into(),
+            private_key: bus_key(seed).0,
+            sig_type:    SignatureType::Ed25519,
+            peer_keys:   serials.iter().map(|serial| (serial.to_string(), bus_key(2).1)).collect(),
+        }
+    }
+
+    /// Keys for a secondary signing with `seed` that accepts a primary signing with seed 1.
+    fn secondary_keys(serial: &str, seed: u8) -> BusKeys {
+        BusKeys {
+            serial:      serial.into(),
+            private_key: bus_key(seed).0,
+            sig_type:    SignatureType::Ed25519,
+            peer_keys:   hashmap!{ "test-primary".into() => bus_key(1).1 },
+        }
     }
 
     fn timeout(ms: u64) -> Duration {
@@ -989,6 +1667,27 @@ mod tests {
         assert_eq!(primary.aborted(), &hashset!{c});
     }
 
+    #[test]
+    fn atomic_commit_rollback() {
+        let (payloads, srv, ca, cb, cc, a, b, c) = connect("commit_rollback");
+        let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
+        let mut sa = Secondary::new(ca, Box::new(Success), timeout(500), None);
+        let mut sb = Secondary::new(cb, Box::new(Success), timeout(500), None);
+        let mut sc = Secondary::new(cc, Box::new(CommitFail), timeout(500), None);
+        thread::spawn(move || while sa.listen().is_ok() {});
+        thread::spawn(move || while sb.listen().is_ok() {});
+        thread::spawn(move || assert!(sc.listen().is_err()));
+
+        match primary.commit() {
+            Err(Error::AtomicAbort(_)) => (),
+            other => panic!("expected abort, got {:?}", other)
+        }
+        assert_eq!(primary.committed(), &hashset!{a.clone(), b.clone()});
+        assert_eq!(primary.aborted(), &hashset!{c});
+        assert_eq!(primary.rolled_back(), &hashset!{a, b});
+        assert_eq!(primary.into_manifests().len(), 3);
+    }
+
     #[test]
     fn atomic_verify_timeout() {
         let (payloads, srv, ca, cb, cc, a, b, _) = connect("verify_timeout");
@@ -1037,10 +1736,28 @@ mod tests {
         assert_eq!(primary.aborted(), &hashset!{});
     }
 
+    #[test]
+    fn atomic_commit_lost_ack() {
+        let (payloads, srv, ca, cb, cc, a, b, c) = connect("commit_lost_ack");
+        let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(2000), None);
+        let mut sa = Secondary::new(ca, Box::new(Success), timeout(500), None);
+        let mut sb = Secondary::new(cb, Box::new(Success), timeout(500), None);
+        let mut sc = Secondary::new(cc, Box::new(CommitSlow), timeout(500), None);
+        thread::spawn(move || while sa.listen().is_ok() {});
+        thread::spawn(move || while sb.listen().is_ok() {});
+        thread::spawn(move || while sc.listen().is_ok() {});
+
+        assert!(primary.commit().is_err());
+        assert_eq!(primary.committed(), &hashset!{a.clone(), b.clone(), c.clone()});
+        assert_eq!(primary.aborted(), &hashset!{});
+        assert_eq!(primary.rolled_back(), &hashset!{a, b, c});
+        assert_eq!(primary.into_manifests().len(), 3);
+    }
+
     #[test]
     fn atomic_verify_crash() {
-        let (payloads, srv, ca, cb, cc, a, b, c) = connect("verify_crash");
-        let addr = srv._addr.clone();
+        let (payloads, srv, connector, a, b, c) = serve("verify_crash");
+        let (ca, cb, cc) = (client(&a, &connector), client(&b, &connector), client(&c, &connector));
         let serial_c = c.clone();
 
         let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
@@ -1050,13 +1767,13 @@ mod tests {
         thread::spawn(move || assert!(sb.listen().is_ok()));
         thread::spawn(move || {
             let path = format!("/tmp/sota-atomic-verify-crash-{}", time::precise_time_ns().to_string());
-            let outcome = panic::catch_unwind(|| {
+            let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| {
                 let mut sc = Secondary::new(cc, Box::new(VerifyCrash), timeout(500), Some(path.clone()));
                 panic::set_hook(Box::new(|_| ()));
                 assert!(sc.listen().is_err());
-            });
+            }));
             assert!(outcome.is_err());
-            let cc = TcpClient::new(serial_c, addr).expect("cc");
+            let cc = client(&serial_c, &connector);
             let mut sc = Secondary::recover(path, cc, Box::new(Success)).expect("recover");
             assert!(sc.listen().is_ok());
         });
@@ -1068,8 +1785,8 @@ mod tests {
 
     #[test]
     fn atomic_fetch_crash() {
-        let (payloads, srv, ca, cb, cc, a, b, c) = connect("fetch_crash");
-        let addr = srv._addr.clone();
+        let (payloads, srv, connector, a, b, c) = serve("fetch_crash");
+        let (ca, cb, cc) = (client(&a, &connector), client(&b, &connector), client(&c, &connector));
         let serial_c = c.clone();
 
         let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
@@ -1079,13 +1796,13 @@ mod tests {
         thread::spawn(move || assert!(sb.listen().is_ok()));
         thread::spawn(move || {
             let path = format!("/tmp/sota-atomic-fetch-crash-{}", time::precise_time_ns().to_string());
-            let outcome = panic::catch_unwind(|| {
+            let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| {
                 let mut sc = Secondary::new(cc, Box::new(FetchCrash), timeout(500), Some(path.clone()));
                 panic::set_hook(Box::new(|_| ()));
                 assert!(sc.listen().is_err());
-            });
+            }));
             assert!(outcome.is_err());
-            let cc = TcpClient::new(serial_c, addr).expect("cc");
+            let cc = client(&serial_c, &connector);
             let mut sc = Secondary::recover(path, cc, Box::new(Success)).expect("recover");
             assert!(sc.listen().is_ok());
         });
@@ -1097,8 +1814,8 @@ mod tests {
 
     #[test]
     fn atomic_commit_crash() {
-        let (payloads, srv, ca, cb, cc, a, b, c) = connect("commit_crash");
-        let addr = srv._addr.clone();
+        let (payloads, srv, connector, a, b, c) = serve("commit_crash");
+        let (ca, cb, cc) = (client(&a, &connector), client(&b, &connector), client(&c, &connector));
         let serial_c = c.clone();
 
         let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
@@ -1108,13 +1825,13 @@ mod tests {
         thread::spawn(move || assert!(sb.listen().is_ok()));
         thread::spawn(move || {
             let path = format!("/tmp/sota-atomic-commit-crash-{}", time::precise_time_ns().to_string());
-            let outcome = panic::catch_unwind(|| {
+            let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| {
                 let mut sc = Secondary::new(cc, Box::new(CommitCrash), timeout(500), Some(path.clone()));
                 panic::set_hook(Box::new(|_| ()));
                 assert!(sc.listen().is_err());
-            });
+            }));
             assert!(outcome.is_err());
-            let cc = TcpClient::new(serial_c, addr).expect("cc");
+            let cc = client(&serial_c, &connector);
             let mut sc = Secondary::recover(path, cc, Box::new(Success)).expect("recover");
             assert!(sc.listen().is_ok());
         });
@@ -1124,6 +1841,77 @@ mod tests {
         assert_eq!(primary.aborted(), &hashset!{});
     }
 
+    #[test]
+    fn atomic_primary_crash_start() {
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_start", &[], State::Start);
+        assert!(outcome.is_ok());
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (3, 0, 3));
+    }
+
+    #[test]
+    fn atomic_primary_crash_verify() {
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_verify", &[State::Start], State::Verify);
+        assert!(outcome.is_ok());
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (3, 0, 3));
+    }
+
+    #[test]
+    fn atomic_primary_crash_fetch() {
+        let before = [State::Start, State::Verify];
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_fetch", &before, State::Fetch);
+        assert!(outcome.is_ok());
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (3, 0, 3));
+    }
+
+    #[test]
+    fn atomic_primary_crash_commit() {
+        let before = [State::Start, State::Verify, State::Fetch];
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_commit", &before, State::Commit);
+        assert!(outcome.is_ok());
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (3, 0, 3));
+    }
+
+    #[test]
+    fn atomic_primary_crash_abort() {
+        let before = [State::Start, State::Verify];
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_abort", &before, State::Abort);
+        match outcome {
+            Err(Error::AtomicAbort(_)) => (),
+            other => panic!("expected abort, got {:?}", other)
+        }
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (0, 3, 3));
+    }
+
+    #[test]
+    fn atomic_primary_crash_rollback() {
+        let before = [State::Start, State::Verify, State::Fetch, State::Commit];
+        let (outcome, committed, aborted, manifests) = primary_crash("primary_crash_rollback", &before, State::Rollback);
+        match outcome {
+            Err(Error::AtomicAbort(_)) => (),
+            other => panic!("expected abort, got {:?}", other)
+        }
+        assert_eq!((committed.len(), aborted.len(), manifests.len()), (3, 0, 3));
+    }
+
+    #[test]
+    fn atomic_secondary_finished() {
+        let (payloads, srv, connector, a, b, c) = serve("secondary_finished");
+        let path = format!("/tmp/sota-atomic-recover-{}", c);
+        let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
+        for serial in &[a.clone(), b.clone()] {
+            keep_listening(serial.clone(), connector.clone());
+        }
+        let mut sc = Secondary::resume(client(&c, &connector), Box::new(Success), timeout(500), Some(path.clone())).expect("resume");
+        let listener = thread::spawn(move || assert!(sc.listen().is_ok()));
+        assert!(primary.commit().is_ok());
+        listener.join().expect("join listener");
+
+        let sc = Secondary::resume(client(&c, &connector), Box::new(Success), timeout(500), Some(path)).expect("resume");
+        assert_eq!(sc.state, State::Idle);
+        assert_eq!(sc.finished.as_ref().map(|finished| (finished.txid, finished.state)), Some((primary.txid, State::Commit)));
+        assert_eq!(primary.committed(), &hashset!{a, b, c});
+    }
+
     #[test]
     fn atomic_fetch_image() {
         let mut buf = [0; 123];
@@ -1158,4 +1946,207 @@ mod tests {
         assert_eq!(primary.committed(), &hashset!{a, b, c});
         assert_eq!(primary.aborted(), &hashset!{});
     }
+
+    #[test]
+    fn atomic_chunk_window() {
+        let mut buf = vec![0; CHUNK_SIZE * (CHUNK_WINDOW + 4)];
+        SystemRandom::new().fill(&mut buf).expect("fill buf");
+        let image_name = format!("test-image-chunk-window-{}", time::precise_time_ns());
+        let image_dir = format!("/tmp/sota-test-image-{}", time::precise_time_ns());
+        fs::create_dir_all(&image_dir).expect("create dir");
+        Util::write_file(&format!("{}/{}", image_dir, image_name), &buf).expect("write buf");
+        let mut reader = ImageReader::new(image_name.clone(), image_dir).expect("reader");
+        let meta = reader.image_meta().expect("meta");
+
+        let (_, srv, connector, a, _, _) = serve("chunk_window");
+        let mut secondary = Secondary::new(client(&a, &connector), Box::new(FetchImage), timeout(10000), None);
+        thread::spawn(move || { let _ = secondary.listen(); });
+
+        let txid = Uuid::new_v4();
+        let bytes = Bytes::from(json::to_vec(&meta).expect("json"));
+        for msg in vec![
+            SecondaryMessage::Start { txid: txid },
+            SecondaryMessage::Step { txid: txid, state: State::Verify, payload: None },
+        ] {
+            srv.write_message(&a, &msg).expect("write step");
+            match srv.read_message(timeout(1000)) {
+                Some((_, PrimaryMessage::Ack { .. })) => (),
+                other => panic!("expected an ack, got {:?}", other)
+            }
+        }
+        let fetch = SecondaryMessage::Step { txid: txid, state: State::Fetch, payload: Some(Payload::ImageMeta(bytes)) };
+        srv.write_message(&a, &fetch).expect("write fetch");
+
+        let requested = |timeout: Duration| {
+            let mut indices = Vec::new();
+            while let Some((_, msg)) = srv.read_message(if indices.is_empty() { timeout } else { Duration::from_millis(50) }) {
+                match msg {
+                    PrimaryMessage::Chunk { index, .. } => indices.push(index),
+                    other => panic!("expected a chunk request, got {:?}", other)
+                }
+            }
+            indices
+        };
+        let window = (0..CHUNK_WINDOW as u64).collect::<Vec<_>>();
+        assert_eq!(requested(timeout(1000)), window, "initial window");
+        assert_eq!(requested(timeout(2000)), window, "requested again after a read timeout");
+
+        let chunk = Bytes::from(&buf[..CHUNK_SIZE]);
+        srv.write_message(&a, &SecondaryMessage::Chunk { txid: txid, image: image_name, index: 0, chunk: chunk }).expect("write chunk");
+        assert_eq!(requested(timeout(1000)), vec![CHUNK_WINDOW as u64], "window refilled after a chunk");
+    }
+
+    /// Run with `cargo test -- --ignored atomic_fetch_throughput`.
+    #[test]
+    #[ignore]
+    fn atomic_fetch_throughput() {
+        let size = 8 * 1024 * 1024;
+        let latency = Duration::from_millis(20);
+        let baseline = transfer_image("fetch_throughput_single", size, 1, latency);
+        let windowed = transfer_image("fetch_throughput_window", size, CHUNK_WINDOW, latency);
+        println!("{} chunks over a {:?} link: {:?} with one request outstanding, {:?} with {}",
+                 size / CHUNK_SIZE, latency, baseline, windowed, CHUNK_WINDOW);
+        assert!(windowed < baseline, "a window of {} requests was no faster than a single request", CHUNK_WINDOW);
+    }
+
+    #[test]
+    fn atomic_cipher() {
+        let shared = [7; 32];
+        let (mut primary_reader, mut primary_writer) = Cipher::derive(&shared, &[1; 32], &[2; 32]);
+        let (mut secondary_writer, mut secondary_reader) = Cipher::derive(&shared, &[1; 32], &[2; 32]);
+        let msg = PrimaryMessage::Chunk { txid: Uuid::new_v4(), image: "image".into(), index: 3 };
+
+        let sealed = secondary_writer.seal(&msg).expect("seal");
+        assert!(secondary_reader.open::<PrimaryMessage>(&sealed).is_err());
+        assert_eq!(primary_reader.open::<PrimaryMessage>(&sealed).expect("open"), msg);
+        assert!(primary_reader.open::<PrimaryMessage>(&sealed).is_err());
+
+        let mut tampered = primary_writer.seal(&msg).expect("seal");
+        tampered[0] ^= 1;
+        let (_, mut reader) = Cipher::derive(&shared, &[1; 32], &[2; 32]);
+        assert!(reader.open::<PrimaryMessage>(&tampered).is_err());
+    }
+
+    /// Returns each scripted read in turn, where `None` is a read timeout.
+    struct Stutter(VecDeque<Option<Vec<u8>>>);
+
+    impl Read for Stutter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(mut data)) => {
+                    if data.len() > buf.len() {
+                        let rest = data.split_off(buf.len());
+                        self.0.push_front(Some(rest));
+                    }
+                    buf[..data.len()].copy_from_slice(&data);
+                    Ok(data.len())
+                }
+                Some(None) => Err(io::Error::new(ErrorKind::TimedOut, "timeout")),
+                None => Ok(0)
+            }
+        }
+    }
+
+    #[test]
+    fn atomic_partial_frames() {
+        let shared = [7; 32];
+        let (_, mut writer) = Cipher::derive(&shared, &[1; 32], &[2; 32]);
+        let (_, mut reader) = Cipher::derive(&shared, &[1; 32], &[2; 32]);
+        let first = SecondaryMessage::Start { txid: Uuid::new_v4() };
+        let second = SecondaryMessage::Chunk { txid: Uuid::new_v4(), image: "image".into(), index: 1, chunk: Bytes::from(vec![5; 1000]) };
+        let mut frames = Vec::new();
+        writer.write(&mut frames, &first).expect("write first");
+        writer.write(&mut frames, &second).expect("write second");
+
+        let mut script = VecDeque::new();
+        for piece in frames.chunks(3) {
+            script.push_back(Some(piece.to_vec()));
+            script.push_back(None);
+        }
+        let mut stream = Stutter(script);
+        let mut read = Vec::new();
+        while read.len() < 2 {
+            match reader.read::<SecondaryMessage>(&mut stream) {
+                Ok(msg) => read.push(msg),
+                Err(ref err) if is_closed(err) => panic!("stream closed after {} messages", read.len()),
+                Err(ref err) if should_retry(err) => continue,
+                Err(err) => panic!("read failed: {}", err)
+            }
+        }
+        assert_eq!(read, vec![first, second]);
+    }
+
+    #[test]
+    fn atomic_frame_limit() {
+        let (mut primary_reader, _) = Cipher::derive(&[7; 32], &[1; 32], &[2; 32]);
+        let mut size = [0; 4];
+        BigEndian::write_u32(&mut size, PRIMARY_MAX_BYTES + 1);
+        let mut stream = Stutter(vec![Some(size.to_vec())].into_iter().collect());
+        match primary_reader.read::<PrimaryMessage>(&mut stream) {
+            Err(Error::AtomicPayload) => (),
+            other => panic!("expected payload error, got {:?}", other.map(|_| ()))
+        }
+
+        BigEndian::write_u32(&mut size, HANDSHAKE_MAX_BYTES + 1);
+        let mut stream = Stutter(vec![Some(size.to_vec())].into_iter().collect());
+        assert!(read_handshake(&mut stream).is_err());
+    }
+
+    #[test]
+    fn atomic_rejects_secondary() {
+        let a = format!("rejects_{}_a", time::precise_time_ns());
+        let b = format!("rejects_{}_b", time::precise_time_ns());
+        let srv = BusServer::new(primary_keys(1, &[&a]));
+        let (listener, connector) = channel();
+        srv.listen(listener);
+        let transport = Box::new(connector.connect().expect("connect"));
+        assert!(BusClient::new(&secondary_keys(&b, 2), transport).is_err());
+
+        let transport = Box::new(connector.connect().expect("connect"));
+        let _ = BusClient::new(&secondary_keys(&a, 3), transport).expect("handshake sent");
+        thread::sleep(timeout(500));
+        match srv.write_message(&a, &SecondaryMessage::Start { txid: Uuid::new_v4() }) {
+            Err(Error::AtomicOffline(serial)) => assert_eq!(serial, a),
+            other => panic!("expected {} offline, got {:?}", a, other)
+        }
+    }
+
+    #[test]
+    fn atomic_rejects_primary() {
+        let a = format!("rejects_{}_a", time::precise_time_ns());
+        let srv = BusServer::new(primary_keys(3, &[&a]));
+        let (listener, connector) = channel();
+        srv.listen(listener);
+        match BusClient::new(&secondary_keys(&a, 2), Box::new(connector.connect().expect("connect"))) {
+            Err(Error::AtomicHandshake(_)) => (),
+            Err(err) => panic!("expected handshake error, got {}", err),
+            Ok(_) => panic!("expected handshake error")
+        }
+    }
+
+    #[test]
+    fn atomic_transports() {
+        let (payloads, srv, _, a, b, c) = serve("transports");
+        let tcp = TcpListener::bind("127.0.0.1:0").expect("bind tcp");
+        let tcp_addr = tcp.local_addr().expect("tcp addr");
+        let unix_path = format!("/tmp/sota-atomic-transports-{}.socket", time::precise_time_ns());
+        let unix = UnixListener::bind(&unix_path).expect("bind unix");
+        srv.listen(tcp);
+        srv.listen(unix);
+
+        let ca = BusClient::new(&secondary_keys(&a, 2), Box::new(TcpStream::connect(tcp_addr).expect("tcp"))).expect("ca");
+        let cb = BusClient::new(&secondary_keys(&b, 2), Box::new(UnixStream::connect(&unix_path).expect("unix"))).expect("cb");
+        let cc = BusClient::new(&secondary_keys(&c, 2), Box::new(TcpStream::connect(tcp_addr).expect("tcp"))).expect("cc");
+        let mut primary = Primary::new(payloads, hashmap!{}, &srv, timeout(5000), None);
+        let mut sa = Secondary::new(ca, Box::new(Success), timeout(500), None);
+        let mut sb = Secondary::new(cb, Box::new(Success), timeout(500), None);
+        let mut sc = Secondary::new(cc, Box::new(Success), timeout(500), None);
+        thread::spawn(move || assert!(sa.listen().is_ok()));
+        thread::spawn(move || assert!(sb.listen().is_ok()));
+        thread::spawn(move || assert!(sc.listen().is_ok()));
+
+        assert!(primary.commit().is_ok());
+        assert_eq!(primary.committed(), &hashset!{a, b, c});
+        let _ = fs::remove_file(&unix_path);
+    }
 }
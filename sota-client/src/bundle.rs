@@ -0,0 +1,149 @@
+use chan::Sender;
+use hyper::status::StatusCode;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder};
+use url;
+
+use datatype::{Error, Url, Util};
+use http::{Client, Request, Response, ResponseData};
+
+
+const BUNDLE_DIR: &'static str = "/tmp/sota-bundle";
+
+/// An offline update bundle, either a directory or a tarball of one, laid out as:
+///
+/// ```text
+/// director/   Director repository metadata
+/// repo/       Image repository metadata
+/// targets/    Target images named by refname
+/// ```
+///
+/// Bundles are only installed in Uptane mode so that every image is verified
+/// against the signed metadata. The manifest generated after installation is
+/// written back to `manifest.json`.
+pub struct Bundle {
+    pub path: String,
+    pub dir:  PathBuf,
+}
+
+impl Bundle {
+    /// Open a bundle directory, or unpack a bundle tarball to a temporary directory.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let meta = fs::metadata(path).map_err(|err| Error::Bundle(format!("couldn't open {}: {}", path, err)))?;
+        let dir = if meta.is_dir() {
+            fs::canonicalize(path)?
+        } else {
+            let name = Path::new(path).file_name().ok_or_else(|| Error::Bundle(format!("no file name: {}", path)))?;
+            let dir = Path::new(BUNDLE_DIR).join(name);
+            if dir.exists() { fs::remove_dir_all(&dir)?; }
+            fs::create_dir_all(&dir)?;
+            Archive::new(File::open(path)?).unpack(&dir)?;
+            dir
+        };
+        info!("Opened bundle {} at {}", path, dir.display());
+        Ok(Bundle { path: path.into(), dir: dir })
+    }
+
+    /// Returns whether the bundle was unpacked from a tarball.
+    pub fn is_archive(&self) -> bool {
+        Path::new(&self.path).is_file()
+    }
+
+    /// Returns a `file://` URL for a directory within the bundle.
+    pub fn url(&self, subdir: &str) -> Result<Url, Error> {
+        url::Url::from_directory_path(self.dir.join(subdir))
+            .map(Url)
+            .map_err(|_| Error::Bundle(format!("couldn't make a URL for {}", subdir)))
+    }
+
+    /// Write the generated manifest back into the bundle. A tarball is repacked
+    /// beside the original then renamed over it, so that a failed write leaves
+    /// the original bundle untouched.
+    pub fn write_manifest(&self, manifest: &[u8]) -> Result<(), Error> {
+        Util::write_file(&self.dir.join("manifest.json").to_string_lossy(), manifest)?;
+        if self.is_archive() {
+            let temp = format!("{}.tmp", self.path);
+            let repacked = File::create(&temp).map_err(Error::from).and_then(|file| {
+                let mut builder = Builder::new(file);
+                builder.append_dir_all("", &self.dir)?;
+                Ok(builder.finish()?)
+            });
+            if let Err(err) = repacked {
+                let _ = fs::remove_file(&temp);
+                return Err(err);
+            }
+            fs::rename(&temp, &self.path)?;
+        }
+        info!("Wrote manifest to bundle {}", self.path);
+        Ok(())
+    }
+}
+
+
+/// A `Client` that serves `file://` URLs from disk, so that bundled metadata
+/// and images are fetched and verified in the same way as from a server.
+pub struct BundleClient;
+
+impl Client for BundleClient {
+    fn chan_request(&self, req: Request, resp_tx: Sender<Response>) {
+        let path = match req.url.0.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return resp_tx.send(Response::Error(Box::new(Error::Bundle(format!("not a file URL: {}", req.url)))))
+        };
+        match Util::read_file(&path.to_string_lossy()) {
+            Ok(body) => resp_tx.send(Response::Success(ResponseData { code: StatusCode::Ok, body: body })),
+            Err(_) => resp_tx.send(Response::Failed(ResponseData { code: StatusCode::NotFound, body: Vec::new() }))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use pacman::test::TestDir;
+
+
+    #[test]
+    fn test_bundle_archive() {
+        let src = TestDir::new("sota-bundle-src");
+        Util::write_file(&format!("{}/director/root.json", src.0), b"root").expect("write root");
+        let dir = TestDir::new("sota-bundle-tar");
+        let path = format!("{}/update.tar", dir.0);
+        {
+            let mut builder = Builder::new(File::create(&path).expect("create tar"));
+            builder.append_dir_all("", &src.0).expect("append");
+            builder.finish().expect("finish");
+        }
+
+        let bundle = Bundle::open(&path).expect("open bundle");
+        assert!(bundle.is_archive());
+        let rx = BundleClient.get(bundle.url("director").expect("url").join("/root.json"), None);
+        match rx.recv().expect("response") {
+            Response::Success(data) => assert_eq!(data.body, b"root"),
+            other => panic!("expected root.json, got {}", other)
+        }
+        match BundleClient.get(bundle.url("director").expect("url").join("/2.root.json"), None).recv().expect("response") {
+            Response::Failed(data) => assert_eq!(data.code, StatusCode::NotFound),
+            other => panic!("expected not found, got {}", other)
+        }
+
+        bundle.write_manifest(b"manifest").expect("write manifest");
+        let mut archive = Archive::new(File::open(&path).expect("open tar"));
+        let mut found = false;
+        for entry in archive.entries().expect("entries") {
+            let mut entry = entry.expect("entry");
+            if entry.path().expect("path") == Path::new("manifest.json") {
+                let mut text = String::new();
+                entry.read_to_string(&mut text).expect("read manifest");
+                assert_eq!(text, "manifest");
+                found = true;
+            }
+        }
+        assert!(found);
+        assert!(! Path::new(&format!("{}.tmp", path)).exists());
+    }
+}
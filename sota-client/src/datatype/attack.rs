@@ -0,0 +1,121 @@
+use json;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use datatype::{Error, Util};
+
+
+/// A per-ECU list of detected attacks that persists until it has been reported.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct AttackLog {
+    #[serde(skip_serializing, skip_deserializing)]
+    path:    Option<String>,
+    attacks: BTreeMap<String, Vec<String>>,
+}
+
+impl AttackLog {
+    /// Load the attacks previously saved to a file, or start an empty log.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let mut log = if Path::new(path).exists() {
+            json::from_slice::<AttackLog>(&Util::read_file(path)?)?
+        } else {
+            AttackLog::default()
+        };
+        log.path = Some(path.into());
+        Ok(log)
+    }
+
+    /// Record a new attack against an ECU serial.
+    pub fn record(&mut self, serial: &str, attack: String) -> Result<(), Error> {
+        warn!("Attack detected for ECU {}: {}", serial, attack);
+        self.attacks.entry(serial.into()).or_insert_with(Vec::new).push(attack);
+        self.save()
+    }
+
+    /// Returns whether any attacks are waiting to be reported.
+    pub fn is_empty(&self) -> bool {
+        self.attacks.values().all(|attacks| attacks.is_empty())
+    }
+
+    /// Returns the attacks recorded against an ECU in `attacks_detected` format.
+    pub fn report(&self, serial: &str) -> String {
+        self.attacks.get(serial).map(|attacks| attacks.join("; ")).unwrap_or_default()
+    }
+
+    /// Returns all recorded attacks with each prefixed by the ECU serial.
+    pub fn report_all(&self) -> String {
+        self.attacks.iter()
+            .flat_map(|(serial, attacks)| attacks.iter().map(move |attack| format!("{}: {}", serial, attack)))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Returns the number of attacks recorded against an ECU.
+    pub fn count(&self, serial: &str) -> usize {
+        self.attacks.get(serial).map_or(0, |attacks| attacks.len())
+    }
+
+    /// Forget the first `count` attacks recorded against an ECU once a report
+    /// containing them has been accepted.
+    pub fn clear_reported(&mut self, serial: &str, count: usize) -> Result<(), Error> {
+        let empty = match self.attacks.get_mut(serial) {
+            Some(attacks) => {
+                let _ = attacks.drain(..cmp::min(count, attacks.len()));
+                attacks.is_empty()
+            }
+            None => return Ok(())
+        };
+        if empty { let _ = self.attacks.remove(serial); }
+        self.save()
+    }
+
+    /// Forget the attacks recorded against an ECU.
+    pub fn clear(&mut self, serial: &str) -> Result<(), Error> {
+        if self.attacks.remove(serial).is_some() { self.save() } else { Ok(()) }
+    }
+
+    /// Forget all recorded attacks.
+    pub fn clear_all(&mut self) -> Result<(), Error> {
+        self.attacks.clear();
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        if let Some(ref path) = self.path {
+            Util::write_file(path, &json::to_vec(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pacman::test::TestDir;
+
+
+    #[test]
+    fn test_attack_log_persists() {
+        let dir = TestDir::new("sota-attack-log");
+        let path = format!("{}/attacks.json", dir.0);
+
+        let mut log = AttackLog::load(&path).expect("empty log");
+        assert!(log.is_empty());
+        log.record("primary", "rollback".into()).expect("record");
+        log.record("primary", "freeze".into()).expect("record");
+        log.record("secondary", "bad image".into()).expect("record");
+
+        let mut log = AttackLog::load(&path).expect("saved log");
+        assert_eq!(log.report("primary"), "rollback; freeze");
+        assert_eq!(log.report_all(), "primary: rollback; primary: freeze; secondary: bad image");
+        assert_eq!(log.count("primary"), 2);
+        log.clear_reported("primary", 1).expect("clear reported");
+        assert_eq!(AttackLog::load(&path).expect("reported log").report("primary"), "freeze");
+        log.clear("secondary").expect("clear");
+        assert_eq!(AttackLog::load(&path).expect("cleared log").report("secondary"), "");
+        log.clear_all().expect("clear all");
+        assert!(AttackLog::load(&path).expect("empty log").is_empty());
+    }
+}
@@ -5,15 +5,73 @@ use std::collections::BTreeMap;
 use datatype::Error;
 
 
+const MAX_DEPTH: usize = 128;
+
+
+/// Encodes and validates OLPC canonical JSON, where object keys are sorted,
+/// there is no whitespace, only `"` and `\` are escaped in strings, and
+/// numbers are integers.
 pub struct CanonicalJson;
 
 impl CanonicalJson {
+    /// Encode a JSON value in canonical form, failing on any floats.
     pub fn convert(value: json::Value) -> Result<Vec<u8>, Error> {
         let json = JsonValue::from(value)?;
         let mut buf = Vec::new();
         json.write(&mut buf)?;
         Ok(buf)
     }
+
+    /// Parse bytes that must already be canonical JSON, failing on anything
+    /// the encoder wouldn't have produced.
+    pub fn parse(bytes: &[u8]) -> Result<json::Value, Error> {
+        let mut parser = Parser { bytes: bytes, pos: 0 };
+        let value = parser.value(0)?;
+        if parser.pos < bytes.len() {
+            Err(parser.error("trailing data"))
+        } else {
+            Ok(value.into_value())
+        }
+    }
+
+    /// Returns the bytes of a member of a top-level JSON object exactly as
+    /// received, so that the `signed` part of a document can be checked
+    /// with `CanonicalJson::parse` whatever whitespace surrounds it.
+    pub fn member<'a>(bytes: &'a [u8], key: &str) -> Result<&'a [u8], Error> {
+        let mut parser = Parser { bytes: bytes, pos: 0 };
+        let mut found = None;
+        parser.whitespace();
+        parser.expect(b"{")?;
+        parser.whitespace();
+        if parser.peek() != Some(b'}') {
+            loop {
+                parser.whitespace();
+                let start = parser.pos;
+                parser.skip_string()?;
+                let name = &bytes[start+1..parser.pos-1];
+                parser.whitespace();
+                parser.expect(b":")?;
+                parser.whitespace();
+                let start = parser.pos;
+                parser.skip(0)?;
+                if name == key.as_bytes() {
+                    if found.is_some() {
+                        return Err(parser.error(&format!("duplicate `{}` member", key)));
+                    }
+                    found = Some(&bytes[start..parser.pos]);
+                }
+                parser.whitespace();
+                if parser.peek() != Some(b',') { break }
+                parser.pos += 1;
+            }
+        }
+        parser.expect(b"}")?;
+        parser.whitespace();
+        if parser.pos < bytes.len() {
+            return Err(parser.error("trailing data"));
+        }
+        found.ok_or_else(|| Error::Canonical(format!("no `{}` member", key)))
+    }
 }
 
 
@@ -36,6 +94,7 @@ impl JsonValue {
         match value {
             json::Value::Null => Ok(JsonValue::Null),
             json::Value::Bool(b) => Ok(JsonValue::Bool(b)),
+            json::Value::Number(ref n) if n.is_f64() => Err(Error::Canonical(format!("floats are not allowed: {}", n))),
             json::Value::Number(n) => {
                 n.as_i64()
                     .map(JsonNumber::I64)
@@ -64,7 +123,7 @@ impl JsonValue {
             JsonValue::Bool(false) => Ok(buf.extend(b"false")),
             JsonValue::Number(JsonNumber::I64(n)) => Ok(itoa::write(buf, n).map(|_| ())?),
             JsonValue::Number(JsonNumber::U64(n)) => Ok(itoa::write(buf, n).map(|_| ())?),
-            JsonValue::String(ref s) => Ok(Self::write_str(&mut buf, s)?),
+            JsonValue::String(ref s) => Ok(Self::write_str(&mut buf, s)),
             JsonValue::Array(ref arr) => {
                 buf.push(b'[');
                 let mut first = true;
@@ -87,7 +146,7 @@ impl JsonValue {
                     } else {
                         buf.push(b',');
                     }
-                    Self::write_str(&mut buf, key)?;
+                    Self::write_str(&mut buf, key);
                     buf.push(b':');
                     val.write(&mut buf)?;
                 }
@@ -96,16 +155,230 @@ impl JsonValue {
         }
     }
 
-    fn write_str(buf: &mut Vec<u8>, input: &str) -> Result<(), Error> {
-        let val = json::to_value(json::Value::String(input.into()))?;
-        Ok(buf.extend(json::to_string(&val)?.as_bytes()))
+    fn write_str(buf: &mut Vec<u8>, input: &str) {
+        buf.push(b'"');
+        for byte in input.bytes() {
+            if byte == b'"' || byte == b'\\' {
+                buf.push(b'\\');
+            }
+            buf.push(byte);
+        }
+        buf.push(b'"');
+    }
+
+    fn into_value(self) -> json::Value {
+        match self {
+            JsonValue::Null => json::Value::Null,
+            JsonValue::Bool(b) => json::Value::Bool(b),
+            JsonValue::Number(JsonNumber::I64(n)) => json::Value::from(n),
+            JsonValue::Number(JsonNumber::U64(n)) => json::Value::from(n),
+            JsonValue::String(s) => json::Value::String(s),
+            JsonValue::Array(arr) => json::Value::Array(arr.into_iter().map(Self::into_value).collect()),
+            JsonValue::Object(obj) => json::Value::Object(obj.into_iter().map(|(key, val)| (key, val.into_value())).collect()),
+        }
+    }
+}
+
+
+/// A strict parser that only accepts the exact bytes of canonical JSON.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &str) -> Error {
+        Error::Canonical(format!("{} at byte {}", reason, self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, expected: &[u8]) -> Result<(), Error> {
+        if self.bytes[self.pos..].starts_with(expected) {
+            self.pos += expected.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", String::from_utf8_lossy(expected))))
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().map_or(false, |b| b == b' ' || b == b'\t' || b == b'\n' || b == b'\r') {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip over any JSON value, canonical or not.
+    fn skip(&mut self, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        let (close, is_object) = match self.peek() {
+            Some(b'"') => return self.skip_string(),
+            Some(b'[') => (b']', false),
+            Some(b'{') => (b'}', true),
+            _ => {
+                let start = self.pos;
+                while self.peek().map_or(false, is_literal) {
+                    self.pos += 1;
+                }
+                return if self.pos == start { Err(self.error("unexpected character")) } else { Ok(()) };
+            }
+        };
+
+        self.pos += 1;
+        self.whitespace();
+        if self.peek() != Some(close) {
+            loop {
+                self.whitespace();
+                if is_object {
+                    self.skip_string()?;
+                    self.whitespace();
+                    self.expect(b":")?;
+                    self.whitespace();
+                }
+                self.skip(depth + 1)?;
+                self.whitespace();
+                if self.peek() != Some(b',') { break }
+                self.pos += 1;
+            }
+        }
+        self.expect(&[close])
+    }
+
+    fn skip_string(&mut self) -> Result<(), Error> {
+        self.expect(b"\"")?;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<JsonValue, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        match self.peek() {
+            Some(b'n') => self.expect(b"null").map(|_| JsonValue::Null),
+            Some(b't') => self.expect(b"true").map(|_| JsonValue::Bool(true)),
+            Some(b'f') => self.expect(b"false").map(|_| JsonValue::Bool(false)),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b) if b == b'-' || is_digit(b) => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, Error> {
+        let start = self.pos;
+        let negative = self.peek() == Some(b'-');
+        if negative { self.pos += 1; }
+        let digits = self.pos;
+        while self.peek().map_or(false, is_digit) {
+            self.pos += 1;
+        }
+
+        let len = self.pos - digits;
+        if len == 0 {
+            return Err(self.error("expected a digit"));
+        } else if self.bytes[digits] == b'0' && (len > 1 || negative) {
+            return Err(self.error("leading zero or negative zero"));
+        } else if let Some(b) = self.peek() {
+            if b == b'.' || b == b'e' || b == b'E' {
+                return Err(self.error("floats are not allowed"));
+            }
+        }
+
+        let text = String::from_utf8_lossy(&self.bytes[start..self.pos]);
+        let number = if negative {
+            text.parse::<i64>().map(JsonNumber::I64)
+        } else {
+            text.parse::<u64>().map(JsonNumber::U64)
+        };
+        number.map(JsonValue::Number).map_err(|_| self.error("integer out of range"))
     }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect(b"\"")?;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b) if b == b'"' || b == b'\\' => out.push(b),
+                        _ => return Err(self.error("only quotes and backslashes may be escaped")),
+                    }
+                }
+                Some(b) => out.push(b),
+                None => return Err(self.error("unterminated string")),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    fn array(&mut self, depth: usize) -> Result<JsonValue, Error> {
+        self.expect(b"[")?;
+        let mut arr = Vec::new();
+        if self.peek() != Some(b']') {
+            loop {
+                arr.push(self.value(depth + 1)?);
+                if self.peek() != Some(b',') { break }
+                self.pos += 1;
+            }
+        }
+        self.expect(b"]")?;
+        Ok(JsonValue::Array(arr))
+    }
+
+    fn object(&mut self, depth: usize) -> Result<JsonValue, Error> {
+        self.expect(b"{")?;
+        let mut obj = BTreeMap::new();
+        if self.peek() != Some(b'}') {
+            loop {
+                let key = self.string()?;
+                if obj.keys().next_back().map_or(false, |last: &String| *last >= key) {
+                    return Err(self.error(&format!("key `{}` is unsorted or duplicated", key)));
+                }
+                self.expect(b":")?;
+                let val = self.value(depth + 1)?;
+                let _ = obj.insert(key, val);
+                if self.peek() != Some(b',') { break }
+                self.pos += 1;
+            }
+        }
+        self.expect(b"}")?;
+        Ok(JsonValue::Object(obj))
+    }
+}
+
+fn is_digit(b: u8) -> bool {
+    b >= b'0' && b <= b'9'
+}
+
+fn is_literal(b: u8) -> bool {
+    is_digit(b) || (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') || b == b'-' || b == b'+' || b == b'.'
 }
 
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use datatype::Util;
+    use std::fs;
 
 
     #[test]
@@ -140,4 +413,55 @@ mod test {
         input.write(&mut buf).expect("write failed");
         assert_eq!(&buf, &"{\"some key\":[\"some val array\",-1]}".as_bytes());
     }
+
+    #[test]
+    fn canonical_vectors() {
+        // see tests/canonical/README.md and the `source` field of each file
+        for entry in fs::read_dir("tests/canonical").expect("read vectors dir") {
+            let path = entry.expect("dir entry").path();
+            if path.extension().map_or(true, |ext| ext != "json") { continue }
+            let vectors = json::from_slice::<json::Value>(&Util::read_file(&path.to_string_lossy()).unwrap()).unwrap();
+            check_vectors(&vectors);
+        }
+    }
+
+    fn check_vectors(vectors: &json::Value) {
+        for vector in vectors["valid"].as_array().expect("valid vectors") {
+            let canonical = vector["canonical"].as_str().expect("canonical");
+            let encoded = CanonicalJson::convert(vector["value"].clone()).expect("convert");
+            assert_eq!(String::from_utf8(encoded).unwrap(), canonical);
+            assert_eq!(CanonicalJson::parse(canonical.as_bytes()).expect(canonical), vector["value"]);
+        }
+        for vector in vectors["invalid"].as_array().expect("invalid vectors") {
+            let input = vector.as_str().expect("invalid");
+            match CanonicalJson::parse(input.as_bytes()) {
+                Err(Error::Canonical(_)) => (),
+                other => panic!("expected `{}` to be rejected, got {:?}", input, other)
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_member() {
+        let doc = b"{\n  \"signatures\": [ {\"sig\": \"a\\\"}\"} ],\n  \"signed\": {\"_type\":\"Targets\",\"version\":1}\n}\n";
+        assert_eq!(CanonicalJson::member(doc, "signed").expect("signed"), &b"{\"_type\":\"Targets\",\"version\":1}"[..]);
+        assert_eq!(CanonicalJson::member(doc, "signatures").expect("signatures"), &b"[ {\"sig\": \"a\\\"}\"} ]"[..]);
+        assert!(CanonicalJson::member(doc, "other").is_err());
+        assert!(CanonicalJson::member(b"{\"signed\":1,\"signed\":2}", "signed").is_err());
+        assert!(CanonicalJson::member(b"{\"signed\":1} x", "signed").is_err());
+        assert!(CanonicalJson::member(b"[{\"signed\":1}]", "signed").is_err());
+    }
+
+    #[test]
+    fn canonical_rejects() {
+        match CanonicalJson::convert(json::from_str("[1.5]").unwrap()) {
+            Err(Error::Canonical(_)) => (),
+            other => panic!("expected float to be rejected, got {:?}", other)
+        }
+        assert!(CanonicalJson::parse(b"\"\xff\"").is_err());
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH + 2), "]".repeat(MAX_DEPTH + 2));
+        assert!(CanonicalJson::parse(nested.as_bytes()).is_err());
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(CanonicalJson::parse(nested.as_bytes()).is_ok());
+    }
 }
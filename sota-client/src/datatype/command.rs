@@ -17,6 +17,8 @@ pub enum Command {
 
     /// Check for any pending or in-flight updates.
     GetUpdateRequests,
+    /// Install an offline update bundle from a directory or tarball.
+    InstallBundle(String),
 
     /// List the installed packages on the system.
     ListInstalledPackages,
@@ -37,6 +39,8 @@ pub enum Command {
     /// Send an installation report.
     SendInstallReport(InstallReport),
 
+    /// Resume an Uptane transaction that was interrupted by a restart.
+    UptaneResumeInstall,
     /// Send signed reports from ECUs to the Director server.
     UptaneSendManifest(Option<Manifests>),
     /// Install the verified targets.json metadata to their respective ECUs.
@@ -68,6 +72,12 @@ impl FromStr for Command {
                 _ => Err(Error::Command(format!("unexpected GetUpdateRequests args: {:?}", args))),
             },
 
+            "InstallBundle" => match args.len() {
+                0 => Err(Error::Command("usage: InstallBundle <path>".to_string())),
+                1 => Ok(Command::InstallBundle(args[0].into())),
+                _ => Err(Error::Command(format!("unexpected InstallBundle args: {:?}", args))),
+            },
+
             "ListInstalledPackages" => match args.len() {
                 0 => Ok(Command::ListInstalledPackages),
                 _ => Err(Error::Command(format!("unexpected ListInstalledPackages args: {:?}", args))),
@@ -131,6 +141,11 @@ impl FromStr for Command {
                 _ => Err(Error::Command(format!("unexpected StartInstall args: {:?}", args))),
             },
 
+            "UptaneResumeInstall" => match args.len() {
+                0 => Ok(Command::UptaneResumeInstall),
+                _ => Err(Error::Command(format!("unexpected UptaneResumeInstall args: {:?}", args))),
+            },
+
             "UptaneSendManifest" => match args.len() {
                 // FIXME(PRO-1160): args
                 _ => Err(Error::Command(format!("unexpected UptaneSendManifest args: {:?}", args))),
@@ -187,6 +202,14 @@ mod tests {
         assert!("GetUpdateRequests old".parse::<Command>().is_err());
     }
 
+    #[test]
+    fn install_bundle_test() {
+        assert_eq!("InstallBundle /media/usb/update.tar".parse::<Command>().unwrap(),
+                   Command::InstallBundle("/media/usb/update.tar".into()));
+        assert!("InstallBundle".parse::<Command>().is_err());
+        assert!("InstallBundle one two".parse::<Command>().is_err());
+    }
+
     #[test]
     fn list_installed_test() {
         assert_eq!("ListInstalledPackages".parse::<Command>().unwrap(), Command::ListInstalledPackages);
@@ -254,6 +277,12 @@ mod tests {
         assert!(format!("StartInstall {} extra", DEFAULT_UUID).parse::<Command>().is_err());
     }
 
+    #[test]
+    fn uptane_resume_install_test() {
+        assert_eq!("UptaneResumeInstall".parse::<Command>().unwrap(), Command::UptaneResumeInstall);
+        assert!("UptaneResumeInstall now".parse::<Command>().is_err());
+    }
+
     #[test]
     fn uptane_send_manifest_test() {
         assert!("UptaneSendManifest".parse::<Command>().is_err());
@@ -2,9 +2,10 @@ use std::ops::Deref;
 use toml;
 use uuid::Uuid;
 
-use datatype::{Auth, ClientCredentials, Error, SocketAddrV4, Url, Util};
+use datatype::{Auth, ClientCredentials, Error, SignatureType, SocketAddrV4, Url, Util};
 use http::TlsData;
 use pacman::PacMan;
+use transport::TransportType;
 
 
 /// A container for all parsed configs.
@@ -308,16 +309,24 @@ impl Defaultify<DeviceConfig> for ParsedDeviceConfig {
 #[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct EcuConfig {
     pub ecu_serial:      String,
+    pub hardware_id:     Option<String>,
     pub public_key_path: String,
     pub manifest_path:   String,
+    pub signature_type:  SignatureType,
+    pub isotp_request_id:  Option<u32>,
+    pub isotp_response_id: Option<u32>,
 }
 
 impl Default for EcuConfig {
     fn default() -> EcuConfig {
         EcuConfig {
             ecu_serial:      "my-serial".into(),
+            hardware_id:     None,
             public_key_path: "/tmp/my-serial.pub".into(),
             manifest_path:   "/tmp/my-serial.manifest".into(),
+            signature_type:  SignatureType::RsaSsaPss,
+            isotp_request_id:  None,
+            isotp_response_id: None,
         }
     }
 }
@@ -325,8 +334,12 @@ impl Default for EcuConfig {
 #[derive(Deserialize, Default)]
 struct ParsedEcuConfig {
     ecu_serial:      Option<String>,
+    hardware_id:     Option<String>,
     public_key_path: Option<String>,
     manifest_path:   Option<String>,
+    signature_type:  Option<SignatureType>,
+    isotp_request_id:  Option<u32>,
+    isotp_response_id: Option<u32>,
 }
 
 impl Defaultify<EcuConfig> for ParsedEcuConfig {
@@ -334,8 +347,12 @@ impl Defaultify<EcuConfig> for ParsedEcuConfig {
         let default = EcuConfig::default();
         EcuConfig {
             ecu_serial:      self.ecu_serial.unwrap_or(default.ecu_serial),
+            hardware_id:     self.hardware_id.or(default.hardware_id),
             public_key_path: self.public_key_path.unwrap_or(default.public_key_path),
             manifest_path:   self.manifest_path.unwrap_or(default.manifest_path),
+            signature_type:  self.signature_type.unwrap_or(default.signature_type),
+            isotp_request_id:  self.isotp_request_id.or(default.isotp_request_id),
+            isotp_response_id: self.isotp_response_id.or(default.isotp_response_id),
         }
     }
 }
@@ -506,11 +523,22 @@ pub struct UptaneConfig {
     pub director_server:    Url,
     pub repo_server:        Url,
     pub primary_ecu_serial: String,
+    pub hardware_id:        Option<String>,
     pub metadata_path:      String,
     pub private_key_path:   String,
     pub public_key_path:    String,
+    pub signature_type:     SignatureType,
+    pub atomic_transport:   TransportType,
     pub atomic_primary:     SocketAddrV4,
+    pub atomic_socket:      String,
+    pub atomic_can_interface: String,
     pub atomic_timeout_sec: u64,
+    pub atomic_recover_path: Option<String>,
+    pub max_root_size:      u64,
+    pub max_timestamp_size: u64,
+    pub time_server:        Option<Url>,
+    pub time_server_key_path: Option<String>,
+    pub time_server_signature_type: SignatureType,
 }
 
 impl Default for UptaneConfig {
@@ -519,11 +547,22 @@ impl Default for UptaneConfig {
             director_server:    "http://localhost:8001/director".parse().unwrap(),
             repo_server:        "http://localhost:8002/repo".parse().unwrap(),
             primary_ecu_serial: "primary-serial".to_string(),
+            hardware_id:        None,
             metadata_path:      "/usr/local/etc/sota/metadata".to_string(),
             private_key_path:   "/usr/local/etc/sota/ecuprimary.pem".to_string(),
             public_key_path:    "/usr/local/etc/sota/ecuprimary.pub".to_string(),
+            signature_type:     SignatureType::RsaSsaPss,
+            atomic_transport:   TransportType::Tcp,
             atomic_primary:     "127.0.0.1:2310".parse().unwrap(),
+            atomic_socket:      "/tmp/sota-atomic.socket".to_string(),
+            atomic_can_interface: "can0".to_string(),
             atomic_timeout_sec: 300,
+            atomic_recover_path: None,
+            max_root_size:      512000,
+            max_timestamp_size: 16384,
+            time_server:        None,
+            time_server_key_path: None,
+            time_server_signature_type: SignatureType::Ed25519,
         }
     }
 }
@@ -533,11 +572,22 @@ struct ParsedUptaneConfig {
     director_server:    Option<Url>,
     repo_server:        Option<Url>,
     primary_ecu_serial: Option<String>,
+    hardware_id:        Option<String>,
     metadata_path:      Option<String>,
     private_key_path:   Option<String>,
     public_key_path:    Option<String>,
+    signature_type:     Option<SignatureType>,
+    atomic_transport:   Option<TransportType>,
     atomic_primary:     Option<SocketAddrV4>,
+    atomic_socket:      Option<String>,
+    atomic_can_interface: Option<String>,
     atomic_timeout_sec: Option<u64>,
+    atomic_recover_path: Option<String>,
+    max_root_size:      Option<u64>,
+    max_timestamp_size: Option<u64>,
+    time_server:        Option<Url>,
+    time_server_key_path: Option<String>,
+    time_server_signature_type: Option<SignatureType>,
 }
 
 impl Defaultify<UptaneConfig> for ParsedUptaneConfig {
@@ -547,11 +597,22 @@ impl Defaultify<UptaneConfig> for ParsedUptaneConfig {
             director_server:    self.director_server.unwrap_or(default.director_server),
             repo_server:        self.repo_server.unwrap_or(default.repo_server),
             primary_ecu_serial: self.primary_ecu_serial.unwrap_or(default.primary_ecu_serial),
+            hardware_id:        self.hardware_id.or(default.hardware_id),
             metadata_path:      self.metadata_path.unwrap_or(default.metadata_path),
             private_key_path:   self.private_key_path.unwrap_or(default.private_key_path),
             public_key_path:    self.public_key_path.unwrap_or(default.public_key_path),
+            signature_type:     self.signature_type.unwrap_or(default.signature_type),
+            atomic_transport:   self.atomic_transport.unwrap_or(default.atomic_transport),
             atomic_primary:     self.atomic_primary.unwrap_or(default.atomic_primary),
+            atomic_socket:      self.atomic_socket.unwrap_or(default.atomic_socket),
+            atomic_can_interface: self.atomic_can_interface.unwrap_or(default.atomic_can_interface),
             atomic_timeout_sec: self.atomic_timeout_sec.unwrap_or(default.atomic_timeout_sec),
+            atomic_recover_path: self.atomic_recover_path.or(default.atomic_recover_path),
+            max_root_size:      self.max_root_size.unwrap_or(default.max_root_size),
+            max_timestamp_size: self.max_timestamp_size.unwrap_or(default.max_timestamp_size),
+            time_server:        self.time_server.or(default.time_server),
+            time_server_key_path: self.time_server_key_path.or(default.time_server_key_path),
+            time_server_signature_type: self.time_server_signature_type.unwrap_or(default.time_server_signature_type),
         }
     }
 }
@@ -643,8 +704,15 @@ mod tests {
         metadata_path = "/usr/local/etc/sota/metadata"
         private_key_path = "/usr/local/etc/sota/ecuprimary.pem"
         public_key_path = "/usr/local/etc/sota/ecuprimary.pub"
+        signature_type = "rsassa-pss"
+        atomic_transport = "tcp"
         atomic_primary = "127.0.0.1:2310"
+        atomic_socket = "/tmp/sota-atomic.socket"
+        atomic_can_interface = "can0"
         atomic_timeout_sec = 300
+        max_root_size = 512000
+        max_timestamp_size = 16384
+        time_server_signature_type = "ed25519"
         "#;
 
 
@@ -679,6 +747,16 @@ mod tests {
         assert_eq!(Config::load("tests/config/auth.toml").unwrap(), Config::parse(&configs).unwrap());
     }
 
+    #[test]
+    fn hardware_id_config() {
+        let config = Config::parse("[uptane]\nhardware_id = \"primary-hardware\"").unwrap();
+        assert_eq!(config.uptane.hardware_id, Some("primary-hardware".into()));
+
+        let ecus = "[[ecus]]\necu_serial = \"secondary\"\nhardware_id = \"secondary-hardware\"\n";
+        assert_eq!(Config::parse(ecus).unwrap().ecus[0].hardware_id, Some("secondary-hardware".into()));
+        assert_eq!(Config::parse("[[ecus]]\necu_serial = \"secondary\"").unwrap().ecus[0].hardware_id, None);
+    }
+
     #[test]
     fn backwards_compatible_config() {
         let config = Config::load("tests/config/old.toml").unwrap();
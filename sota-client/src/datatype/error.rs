@@ -33,6 +33,8 @@ use interpreter::CommandExec;
 pub enum Error {
     Addr(AddrParseError),
     AtomicAbort(String),
+    AtomicDecrypt,
+    AtomicHandshake(String),
     AtomicOffline(String),
     AtomicPayload,
     AtomicSigned,
@@ -40,6 +42,7 @@ pub enum Error {
     AtomicTimeout,
     Base64(Base64Error),
     Bincode(BincodeError),
+    Bundle(String),
     Canonical(String),
     Client(String),
     Command(String),
@@ -49,11 +52,13 @@ pub enum Error {
     Hex(FromHexError),
     Http(ResponseData),
     HttpAuth(ResponseData),
+    HttpLimit(u64),
     Hyper(HyperError),
     Io(IoError),
     Image(String),
     Int(ParseIntError),
     Json(SerdeJsonError),
+    KeyFormat(String),
     KeyNotFound(String),
     KeySign(String),
     Openssl(OpensslErrors),
@@ -74,12 +79,20 @@ pub enum Error {
     TufKeyType(String),
     TufRole(String),
     TufSigType(String),
+    UptaneEcuSerial(String),
     UptaneExpired,
+    UptaneFetch(String, Box<Error>),
+    UptaneHardwareId(String),
+    UptaneMetaHash(String),
+    UptaneMetaLength(String),
+    UptaneMetaVersion(String),
     UptaneMissingKeys,
+    UptaneMissingMeta(String),
     UptaneMissingRoles,
     UptaneRole(String),
     UptaneTargets(String),
     UptaneThreshold(String),
+    UptaneTime(String),
     UptaneVersion,
     UrlParse(UrlParseError),
     Utf8(Utf8Error),
@@ -92,13 +105,16 @@ impl Display for Error {
         let inner: String = match *self {
             Error::Addr(ref err)        => format!("Address parse error: {}", err),
             Error::AtomicAbort(ref err) => format!("Atomic transaction aborted: {}", err),
+            Error::AtomicDecrypt        => "Atomic message failed decryption".into(),
+            Error::AtomicHandshake(ref err) => format!("Atomic handshake failed: {}", err),
             Error::AtomicOffline(ref serial) => format!("Secondary offline: {}", serial),
             Error::AtomicPayload        => "Transaction payload too large".into(),
-            Error::AtomicSigned         => "Commit or Abort state needs TufSigned".into(),
+            Error::AtomicSigned         => "Commit, Abort or Rollback state needs TufSigned".into(),
             Error::AtomicState(from, to) => format!("Atomic transition invalid: {:?} -> {:?}", from, to),
             Error::AtomicTimeout        => "Transaction timed out".into(),
             Error::Base64(ref err)      => format!("Base64 parse error: {}", err),
             Error::Bincode(ref err)     => format!("Bincode conversion error: {}", err),
+            Error::Bundle(ref err)      => format!("Bundle error: {}", err),
             Error::Canonical(ref err)   => format!("Canonical JSON error: {}", err),
             Error::Client(ref err)      => format!("Http client error: {}", err),
             Error::Command(ref err)     => format!("Unknown Command: {}", err),
@@ -108,11 +124,13 @@ impl Display for Error {
             Error::Hex(ref err)         => format!("Not valid hex data: {}", err),
             Error::Http(ref err)        => format!("HTTP client error: {}", err),
             Error::HttpAuth(ref err)    => format!("HTTP authorization error: {}", err),
+            Error::HttpLimit(limit)     => format!("HTTP response body exceeds {} bytes", limit),
             Error::Hyper(ref err)       => format!("Hyper error: {}", err),
             Error::Io(ref err)          => format!("IO error: {}", err),
             Error::Image(ref err)       => format!("Image error: {}", err),
             Error::Int(ref err)         => format!("Integer parse error: {}", err),
             Error::Json(ref err)        => format!("JSON parse error: {}", err),
+            Error::KeyFormat(ref err)   => format!("Invalid key format: {}", err),
             Error::KeyNotFound(ref err) => format!("Key not found: {}", err),
             Error::KeySign(ref err)     => format!("Key signing error: {}", err),
             Error::Openssl(ref err)     => format!("OpenSSL errors: {}", err),
@@ -133,12 +151,20 @@ impl Display for Error {
             Error::TufKeyType(ref err)  => format!("Invalid TUF key type: {}", err),
             Error::TufRole(ref err)     => format!("Invalid TUF role: {}", err),
             Error::TufSigType(ref err)  => format!("Invalid TUF signature type: {}", err),
+            Error::UptaneEcuSerial(ref err) => format!("Uptane: ECU serial mismatch, {}", err),
             Error::UptaneExpired        => "Uptane: metadata has expired".into(),
+            Error::UptaneFetch(ref serial, ref err) => format!("Uptane: fetch failed for ECU {}, {}", serial, err),
+            Error::UptaneHardwareId(ref err) => format!("Uptane: hardware ID mismatch, {}", err),
+            Error::UptaneMetaHash(ref err) => format!("Uptane: hash mismatch, {}", err),
+            Error::UptaneMetaLength(ref err) => format!("Uptane: length mismatch, {}", err),
+            Error::UptaneMetaVersion(ref err) => format!("Uptane: version mismatch, {}", err),
             Error::UptaneMissingKeys    => "Uptane: missing `keys` field".into(),
+            Error::UptaneMissingMeta(ref err) => format!("Uptane: missing `meta` entry for {}", err),
             Error::UptaneMissingRoles   => "Uptane: missing `roles` field".into(),
             Error::UptaneRole(ref err)  => format!("Uptane role: {}", err),
             Error::UptaneTargets(ref err) => format!("Uptane targets: {}", err),
             Error::UptaneThreshold(ref err) => format!("Uptane metadata: {}", err),
+            Error::UptaneTime(ref err)  => format!("Uptane: invalid time, {}", err),
             Error::UptaneVersion        => "Uptane: metadata version older than current".into(),
             Error::UrlParse(ref err)    => format!("Url parse error: {}", err),
             Error::Utf8(ref err)        => format!("Utf8 error: {}", err),
@@ -44,6 +44,8 @@ pub enum Event {
     InstallFailed(InstallResult),
     /// An installation report was sent.
     InstallReportSent(InstallReport),
+    /// An offline bundle was installed and its manifest written back to it.
+    BundleInstalled(String),
 
     /// An event requesting an update on all installed packages.
     InstalledPackagesNeeded,
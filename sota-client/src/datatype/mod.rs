@@ -1,3 +1,4 @@
+pub mod attack;
 pub mod auth;
 pub mod canonical;
 pub mod command;
@@ -9,9 +10,11 @@ pub mod install;
 pub mod network;
 pub mod ostree;
 pub mod signature;
+pub mod timeserver;
 pub mod tuf;
 pub mod util;
 
+pub use self::attack::AttackLog;
 pub use self::auth::{AccessToken, Auth, ClientCredentials};
 pub use self::canonical::CanonicalJson;
 pub use self::command::Command;
@@ -26,7 +29,8 @@ pub use self::install::{InstallCode, InstallOutcome, InstallReport, InstallResul
 pub use self::network::{Method, SocketAddrV4, Url};
 pub use self::ostree::OstreePackage;
 pub use self::signature::{Signature, SignatureType};
-pub use self::tuf::{EcuCustom, EcuManifests, EcuVersion, Key, KeyType, KeyValue,
-                    Manifests, PrivateKey, RoleData, RoleName, RoleMeta, TufCustom,
-                    TufImage, TufMeta, TufSigned};
+pub use self::timeserver::{TimeAttestation, TimeServer, TrustedTime};
+pub use self::tuf::{DelegatedRole, Delegations, EcuCustom, EcuManifests, EcuVersion,
+                    HashWriter, Key, KeyType, KeyValue, Manifests, PrivateKey, RoleData,
+                    RoleName, RoleMeta, TargetFormat, TufCustom, TufImage, TufMeta, TufSigned};
 pub use self::util::Util;
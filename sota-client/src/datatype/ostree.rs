@@ -3,7 +3,7 @@ use hex::FromHex;
 use json;
 use std::fmt::Debug;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufReader};
 use std::path::Path;
 use std::process::{Command, Output};
@@ -112,6 +112,26 @@ impl OstreePackage {
         }
     }
 
+    /// Remove the deployment of this package while it is pending a reboot so
+    /// that the currently booted deployment remains the default.
+    pub fn rollback(&self) -> Result<(), Error> {
+        self.rollback_pending(NEW_PACKAGE, &mut |args| Ostree::run(args).map(|_| ()))
+    }
+
+    /// Undeploy using the `ostree` runner when `pending` matches this package.
+    fn rollback_pending(&self, pending: &str, ostree: &mut FnMut(&[&str]) -> Result<(), Error>) -> Result<(), Error> {
+        if ! Path::new(pending).exists() {
+            return Ok(debug!("ostree commit {} is not pending", self.commit));
+        }
+        let latest: OstreePackage = json::from_reader(BufReader::new(File::open(pending)?))?;
+        if latest.commit != self.commit {
+            return Ok(debug!("ostree commit {} is not pending", self.commit));
+        }
+        debug!("rolling back ostree commit {}", self.commit);
+        ostree(&["admin", "undeploy", "0"])?;
+        Ok(fs::remove_file(pending)?)
+    }
+
     /// Get the latest OSTree package (including any new updates pending a reboot).
     pub fn get_latest(serial: &str) -> Result<OstreePackage, Error> {
         if Path::new(NEW_PACKAGE).exists() {
@@ -233,6 +253,7 @@ impl OstreeBranch {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pacman::test::TestDir;
 
 
     const OSTREE_ADMIN_STATUS: &'static str = r#"
@@ -254,4 +275,37 @@ mod tests {
         assert_eq!(branches[1].package.commit, "ce19c41036cc45e49b0cecf6b157523c2105c4de1c");
         assert_eq!(branches[1].package.refName, "<branch>-ce19c41036cc45e49b0cecf6b157523c2105c4de1c");
     }
+
+    #[test]
+    fn rollback_pending() {
+        let dir = TestDir::new("sota-ostree-rollback");
+        let pending = format!("{}/sota-package", dir.0);
+        let treehub = "http://localhost".parse().expect("treehub");
+        let pkg = OstreePackage::new("serial".into(), "ref".into(), "abc123".into(), &treehub);
+        let other = OstreePackage::new("serial".into(), "ref".into(), "def456".into(), &treehub);
+
+        let mut commands = Vec::new();
+        {
+            let mut record = |args: &[&str]| Ok(commands.push(args.join(" ")));
+            pkg.rollback_pending(&pending, &mut record).expect("nothing pending");
+            Util::write_file(&pending, &json::to_vec(&other).expect("other json")).expect("write other");
+            pkg.rollback_pending(&pending, &mut record).expect("other pending");
+            assert!(Path::new(&pending).exists());
+        }
+        assert!(commands.is_empty());
+
+        {
+            let mut record = |args: &[&str]| Ok(commands.push(args.join(" ")));
+            Util::write_file(&pending, &json::to_vec(&pkg).expect("pkg json")).expect("write pkg");
+            pkg.rollback_pending(&pending, &mut record).expect("rollback");
+            assert!(! Path::new(&pending).exists());
+        }
+        assert_eq!(commands, vec!["admin undeploy 0".to_string()]);
+
+        Util::write_file(&pending, &json::to_vec(&pkg).expect("pkg json")).expect("write pkg");
+        match pkg.rollback_pending(&pending, &mut |_| Err(Error::OSTree("undeploy failed".into()))) {
+            Err(Error::OSTree(_)) => assert!(Path::new(&pending).exists()),
+            other => panic!("expected the undeploy error, got {:?}", other)
+        }
+    }
 }
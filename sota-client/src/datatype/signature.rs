@@ -1,11 +1,14 @@
 use crypto::ed25519;
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
+use hex::{FromHex, ToHex};
 use json;
+use openssl::bn::BigNumContext;
+use openssl::ec::{self, EcGroup, EcKey, EcPoint};
 use openssl::hash::MessageDigest;
+use openssl::nid;
 use openssl::pkey::PKey;
 use openssl::rsa::{Rsa, Padding};
-use openssl::sign::Verifier;
+use openssl::sign::{Signer, Verifier};
+use pem;
 use ring::rand::SystemRandom;
 use ring::signature::{RSAKeyPair, RSASigningState, RSA_PSS_SHA256};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -15,11 +18,15 @@ use std::str::FromStr;
 use std::sync::Arc;
 use untrusted::Input;
 
-use datatype::{EcuVersion, Error, PrivateKey, TufSigned, Util};
+use datatype::{EcuVersion, Error, Key, KeyType, KeyValue, PrivateKey, TufSigned, Util};
 
 
 const RSA_PKCS1_PSS_PADDING: c_int = 6;
 
+/// DER prefixes of PKCS#8 private keys and SubjectPublicKeyInfo public keys for Ed25519.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+const ED25519_SPKI_PREFIX:  [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Signature {
@@ -31,6 +38,7 @@ pub struct Signature {
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum SignatureType {
+    Ecdsa,
     Ed25519,
     RsaSsaPss,
 }
@@ -45,6 +53,7 @@ impl<'de> Deserialize<'de> for SignatureType {
 impl Serialize for SignatureType {
     fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
         ser.serialize_str(match *self {
+            SignatureType::Ecdsa     => "ecdsa-sha2-nistp256",
             SignatureType::Ed25519   => "ed25519",
             SignatureType::RsaSsaPss => "rsassa-pss"
         })
@@ -56,6 +65,7 @@ impl FromStr for SignatureType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "ecdsa-sha2-nistp256" => Ok(SignatureType::Ecdsa),
             "ed25519"    => Ok(SignatureType::Ed25519),
             "rsassa-pss" => Ok(SignatureType::RsaSsaPss),
             _ => Err(Error::TufSigType(s.to_string()))
@@ -64,8 +74,94 @@ impl FromStr for SignatureType {
 }
 
 impl SignatureType {
+    /// Returns the TUF key type used for this signature type.
+    pub fn key_type(&self) -> KeyType {
+        match *self {
+            SignatureType::Ecdsa     => KeyType::Ecdsa,
+            SignatureType::Ed25519   => KeyType::Ed25519,
+            SignatureType::RsaSsaPss => KeyType::Rsa,
+        }
+    }
+
+    /// Convert a PEM or DER encoded private key into the format used by `sign_msg`.
+    pub fn private_key(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match *self {
+            SignatureType::Ecdsa => {
+                let pkey = PKey::private_key_from_pem(data).or_else(|_| PKey::private_key_from_der(data))?;
+                Ok(pkey.ec_key()?.private_key_to_der()?)
+            }
+
+            SignatureType::Ed25519 => {
+                let der = pem::parse(data).map(|pem| pem.contents).unwrap_or_else(|_| data.to_vec());
+                match der.len() {
+                    64 => Ok(der),
+                    32 => Ok(ed25519::keypair(&der).0.to_vec()),
+                    48 if der.starts_with(&ED25519_PKCS8_PREFIX) => Ok(ed25519::keypair(&der[16..]).0.to_vec()),
+                    len => Err(Error::KeyFormat(format!("unknown {} byte ed25519 private key", len)))
+                }
+            }
+
+            SignatureType::RsaSsaPss => {
+                let pkey = PKey::private_key_from_pem(data).or_else(|_| PKey::private_key_from_der(data))?;
+                Ok(pkey.rsa()?.private_key_to_der()?)
+            }
+        }
+    }
+
+    /// Convert a PEM, DER or hex encoded public key into a TUF `Key`.
+    pub fn public_key(&self, data: &[u8]) -> Result<Key, Error> {
+        let public = match *self {
+            SignatureType::Ecdsa | SignatureType::RsaSsaPss => {
+                let pkey = PKey::public_key_from_pem(data).or_else(|_| PKey::public_key_from_der(data))?;
+                String::from_utf8(pkey.public_key_to_pem()?)?
+            }
+
+            SignatureType::Ed25519 => {
+                let der = if let Ok(pem) = pem::parse(data) {
+                    pem.contents
+                } else if let Ok(bytes) = Vec::from_hex(String::from_utf8_lossy(data).trim()) {
+                    bytes
+                } else {
+                    data.to_vec()
+                };
+                match der.len() {
+                    32 => der.to_hex(),
+                    44 if der.starts_with(&ED25519_SPKI_PREFIX) => (&der[12..]).to_hex(),
+                    len => return Err(Error::KeyFormat(format!("unknown {} byte ed25519 public key", len)))
+                }
+            }
+        };
+        Ok(Key { keytype: self.key_type(), keyval: KeyValue { public: public } })
+    }
+
+    /// Derive the TUF `Key` for a private key in the format returned by `private_key`.
+    pub fn public_from_private(&self, der_key: &[u8]) -> Result<Key, Error> {
+        let public = match *self {
+            SignatureType::Ecdsa => {
+                let pkey = PKey::from_ec_key(EcKey::private_key_from_der(der_key)?)?;
+                String::from_utf8(pkey.public_key_to_pem()?)?
+            }
+
+            SignatureType::Ed25519 if der_key.len() == 64 => (&der_key[32..]).to_hex(),
+            SignatureType::Ed25519 => return Err(Error::KeyFormat(format!("unknown {} byte ed25519 private key", der_key.len()))),
+
+            SignatureType::RsaSsaPss => {
+                let pkey = PKey::from_rsa(Rsa::private_key_from_der(der_key)?)?;
+                String::from_utf8(pkey.public_key_to_pem()?)?
+            }
+        };
+        Ok(Key { keytype: self.key_type(), keyval: KeyValue { public: public } })
+    }
+
     pub fn sign_msg(&self, msg: &[u8], der_key: &[u8]) -> Result<Vec<u8>, Error> {
         match *self {
+            SignatureType::Ecdsa => {
+                let pkey = PKey::private_key_from_der(der_key)?;
+                let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+                signer.update(msg)?;
+                Ok(signer.finish()?)
+            }
+
             SignatureType::Ed25519 => Ok(ed25519::signature(msg, der_key).as_ref().into()),
 
             SignatureType::RsaSsaPss => {
@@ -80,6 +176,16 @@ impl SignatureType {
 
     pub fn verify_msg(&self, msg: &[u8], der_key: &[u8], sig: &[u8]) -> bool {
         match *self {
+            SignatureType::Ecdsa => {
+                let verify = || -> Result<bool, Error> {
+                    let pub_key = PKey::from_ec_key(p256_public_key(der_key)?)?;
+                    let mut verifier = Verifier::new(MessageDigest::sha256(), &pub_key)?;
+                    verifier.update(msg)?;
+                    Ok(verifier.finish(sig)?)
+                };
+                verify().unwrap_or_else(|err| { trace!("ECDSA verification failed: {}", err); false })
+            }
+
             SignatureType::Ed25519 => ed25519::verify(msg, der_key, sig),
 
             SignatureType::RsaSsaPss => {
@@ -96,13 +202,26 @@ impl SignatureType {
     }
 
     pub fn sign_manifest(&self, manifest: EcuVersion, private_key_path: &str) -> Result<TufSigned, Error> {
-        let mut hasher = Sha256::new();
-        hasher.input(&json::to_vec(&manifest)?);
-        let key = PrivateKey { keyid: hasher.result_str(), der_key: Util::read_file(private_key_path)? };
+        let key = PrivateKey::from_der(self.private_key(&Util::read_file(private_key_path)?)?, *self)?;
         key.sign_data(json::to_value(manifest)?, *self)
     }
 }
 
+/// Parse a DER-encoded public key, rejecting anything but an EC key on P-256.
+fn p256_public_key(der_key: &[u8]) -> Result<EcKey, Error> {
+    let ec_key = PKey::public_key_from_der(der_key)?.ec_key()?;
+    let (group, point) = match (ec_key.group(), ec_key.public_key()) {
+        (Some(group), Some(point)) => (group, point),
+        _ => return Err(Error::KeyFormat("EC public key without a group or point".into()))
+    };
+    let p256 = EcGroup::from_curve_name(nid::X9_62_PRIME256V1)?;
+    let mut ctx = BigNumContext::new()?;
+    let bytes = point.to_bytes(group, ec::POINT_CONVERSION_UNCOMPRESSED, &mut ctx)?;
+    let point = EcPoint::from_bytes(&p256, &bytes, &mut ctx)
+        .map_err(|_| Error::KeyFormat("EC public key is not on the P-256 curve".into()))?;
+    Ok(EcKey::from_public_key(&p256, &point)?)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -134,6 +253,66 @@ mod tests {
         sign_and_verify(SignatureType::RsaSsaPss, &pri_key, &pub_key);
     }
 
+    #[test]
+    fn test_ecdsa_sign_and_verify() {
+        let pri_key = Util::read_file("tests/keys/ecdsa.der").expect("ecdsa.der");
+        let pub_pem = Util::read_file("tests/keys/ecdsa.pub").expect("ecdsa.pub");
+        let pub_key = pem::parse(&pub_pem).expect("pem").contents;
+        sign_and_verify(SignatureType::Ecdsa, &pri_key, &pub_key);
+
+        let key = SignatureType::Ecdsa.public_key(&pub_pem).expect("public key");
+        assert_eq!(SignatureType::Ecdsa.public_from_private(&pri_key).expect("from private"), key);
+    }
+
+    #[test]
+    fn test_ecdsa_rejects_other_keys() {
+        let msg = b"hello";
+        let sign = |key: &PKey| {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            signer.update(msg).unwrap();
+            signer.finish().unwrap()
+        };
+        for curve in &[nid::SECP384R1, nid::SECP256K1] {
+            let group = EcGroup::from_curve_name(*curve).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            let sig = sign(&key);
+            assert!(!SignatureType::Ecdsa.verify_msg(msg, &key.public_key_to_der().unwrap(), &sig));
+        }
+
+        let rsa_pub = pem::parse(Util::read_file("tests/keys/rsa.pub").unwrap()).unwrap().contents;
+        assert!(p256_public_key(&rsa_pub).is_err());
+        let ecdsa_pub = pem::parse(Util::read_file("tests/keys/ecdsa.pub").unwrap()).unwrap().contents;
+        assert!(p256_public_key(&ecdsa_pub).is_ok());
+    }
+
+    #[test]
+    fn test_rsa_key_formats() {
+        let der_key = Util::read_file("tests/keys/rsa.der").expect("rsa.der");
+        let pem_key = PKey::private_key_from_der(&der_key).unwrap().private_key_to_pem().unwrap();
+        assert_eq!(SignatureType::RsaSsaPss.private_key(&der_key).expect("der"), der_key);
+        assert_eq!(SignatureType::RsaSsaPss.private_key(&pem_key).expect("pem"), der_key);
+
+        let pub_pem = Util::read_text("tests/keys/rsa.pub").expect("rsa.pub");
+        let pub_der = pem::parse(&pub_pem).unwrap().contents;
+        let key = Key { keytype: KeyType::Rsa, keyval: KeyValue { public: pub_pem } };
+        let from_der = SignatureType::RsaSsaPss.public_key(&pub_der).expect("public der");
+        assert_eq!(from_der.key_id().unwrap(), key.key_id().unwrap());
+        let from_private = SignatureType::RsaSsaPss.public_from_private(&der_key).expect("from private");
+        assert_eq!(from_private.key_id().unwrap(), key.key_id().unwrap());
+    }
+
+    #[test]
+    fn test_ed25519_key_formats() {
+        let pri_key = SignatureType::Ed25519.private_key(&Util::read_file("tests/keys/ed25519.pem").unwrap()).expect("pem");
+        let key = SignatureType::Ed25519.public_key(&Util::read_file("tests/keys/ed25519.pub").unwrap()).expect("pub");
+        assert_eq!(key.keyval.public, "b797ec99106768d786790e433e6443d8973865ac2d4b2d9a921073872de015bf");
+        let from_hex = SignatureType::Ed25519.public_key(key.keyval.public.as_bytes()).expect("hex");
+        assert_eq!(from_hex.key_id().unwrap(), key.key_id().unwrap());
+        assert_eq!(SignatureType::Ed25519.public_from_private(&pri_key).expect("from private"), key);
+        let pub_key = Vec::from_hex(&key.keyval.public).unwrap();
+        sign_and_verify(SignatureType::Ed25519, &pri_key, &pub_key);
+    }
+
     #[test]
     fn test_ed25519_sign_and_verify() {
         let pri_key = base64::decode("0wm+qYNKH2v7VUMy0lEz0ZfOEtEbdbDNwklW5PPLs4WpCLVDpXuapnO3XZQ9i1wV3aiIxi1b5TxVeVeulbyUyw==").expect("pri_key");
@@ -0,0 +1,108 @@
+use chrono::{DateTime, TimeZone, Utc};
+use json;
+use std::path::Path;
+
+use datatype::{Error, Key, Url, Util};
+
+
+/// A signed statement of the current time that includes the requested nonces.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TimeAttestation {
+    pub nonces: Vec<String>,
+    pub time:   DateTime<Utc>,
+}
+
+
+/// A time server that signs attestations of the current time with its own key.
+///
+/// Attestations are requested with `GET <url>?nonce=<hex>` and are expected as
+/// a `TimeAttestation` inside TUF signed metadata.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TimeServer {
+    pub url: Url,
+    pub key: Key,
+}
+
+impl TimeServer {
+    /// Returns the URL to request an attestation for a nonce.
+    pub fn request_url(&self, nonce: &str) -> Url {
+        let mut url = self.url.clone();
+        url.0.query_pairs_mut().append_pair("nonce", nonce);
+        url
+    }
+}
+
+
+/// The last time attested by the time server, which only ever moves forward.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TrustedTime {
+    #[serde(skip_serializing, skip_deserializing)]
+    path:         Option<String>,
+    pub previous: DateTime<Utc>,
+    pub current:  DateTime<Utc>,
+}
+
+impl Default for TrustedTime {
+    fn default() -> Self {
+        TrustedTime { path: None, previous: Utc.timestamp(0, 0), current: Utc.timestamp(0, 0) }
+    }
+}
+
+impl TrustedTime {
+    /// Load the last trusted time saved to a file, or start from the epoch.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let mut time = if Path::new(path).exists() {
+            json::from_slice::<TrustedTime>(&Util::read_file(path)?)?
+        } else {
+            TrustedTime::default()
+        };
+        time.path = Some(path.into());
+        Ok(time)
+    }
+
+    /// Returns whether any time has been attested yet.
+    pub fn is_set(&self) -> bool {
+        self.current > Utc.timestamp(0, 0)
+    }
+
+    /// Advance to a newly attested time, rejecting any time in the past.
+    pub fn update(&mut self, time: DateTime<Utc>) -> Result<(), Error> {
+        if time < self.current {
+            return Err(Error::UptaneTime(format!("attested time {} is before trusted time {}", time, self.current)));
+        }
+        self.previous = self.current;
+        self.current = time;
+        if let Some(ref path) = self.path {
+            Util::write_file(path, &json::to_vec(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pacman::test::TestDir;
+
+
+    #[test]
+    fn test_trusted_time_monotonic() {
+        let dir = TestDir::new("sota-trusted-time");
+        let path = format!("{}/time.json", dir.0);
+
+        let mut time = TrustedTime::load(&path).expect("default time");
+        assert!(!time.is_set());
+        time.update(Utc.ymd(2017, 10, 1).and_hms(0, 0, 0)).expect("first update");
+        time.update(Utc.ymd(2017, 10, 2).and_hms(0, 0, 0)).expect("second update");
+
+        let mut time = TrustedTime::load(&path).expect("saved time");
+        assert_eq!(time.previous, Utc.ymd(2017, 10, 1).and_hms(0, 0, 0));
+        assert_eq!(time.current, Utc.ymd(2017, 10, 2).and_hms(0, 0, 0));
+        match time.update(Utc.ymd(2017, 9, 1).and_hms(0, 0, 0)) {
+            Err(Error::UptaneTime(_)) => (),
+            other => panic!("expected time error, got {:?}", other)
+        }
+        assert_eq!(TrustedTime::load(&path).expect("unchanged time").current, Utc.ymd(2017, 10, 2).and_hms(0, 0, 0));
+    }
+}
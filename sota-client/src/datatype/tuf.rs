@@ -1,15 +1,17 @@
 use base64;
 use chrono::{DateTime, Utc};
 use crypto::digest::Digest;
-use crypto::sha2::Sha256;
+use crypto::sha2::{Sha256, Sha512};
 use json;
 use pem;
+use serde::{Serialize, Serializer};
 use serde::de::{Deserialize, Deserializer, Error as SerdeError};
 use std::fmt::{self, Display, Formatter};
 use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
 use std::str::FromStr;
 
-use datatype::{CanonicalJson, Error, InstallResult, Signature, SignatureType};
+use datatype::{CanonicalJson, Error, InstallResult, Signature, SignatureType, Util};
 
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
@@ -29,32 +31,153 @@ pub struct TufMeta {
     pub length: u64,
     pub hashes: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<TufCustom>,
 }
 
 impl TufMeta {
     pub fn from(hash_type: String, commit: String) -> Self {
-        TufMeta { length: 0, hashes: hashmap!{ hash_type => commit }, custom: None }
+        TufMeta { length: 0, hashes: hashmap!{ hash_type => commit }, version: None, custom: None }
+    }
+
+    /// Verify that the data matches the expected length and all known hashes.
+    pub fn verify(&self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let mut writer = HashWriter::new(io::sink());
+        writer.write_all(data)?;
+        writer.verify(name, self)
+    }
+
+    /// Verify that another description of the same file agrees on length and hashes.
+    pub fn verify_meta(&self, name: &str, other: &TufMeta) -> Result<(), Error> {
+        if self.length != other.length {
+            return Err(Error::UptaneMetaLength(format!("{} expected {} bytes, got {}", name, other.length, self.length)));
+        }
+
+        let mut checked = 0;
+        for (method, hash) in &self.hashes {
+            if let Some(expected) = other.hashes.get(method) {
+                if hash.to_lowercase() != expected.to_lowercase() {
+                    return Err(Error::UptaneMetaHash(format!("{} {} mismatch: expected {}, got {}", name, method, expected, hash)));
+                }
+                checked += 1;
+            }
+        }
+
+        if checked == 0 {
+            Err(Error::UptaneMetaHash(format!("{} has no hashes in common", name)))
+        } else {
+            Ok(())
+        }
     }
 }
 
+/// Hashes and counts the data written through it so that it can be checked
+/// against a `TufMeta` without buffering it in memory.
+pub struct HashWriter<W: Write> {
+    inner:  W,
+    length: u64,
+    sha256: Sha256,
+    sha512: Sha512,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashWriter { inner: inner, length: 0, sha256: Sha256::new(), sha512: Sha512::new() }
+    }
+
+    /// Verify that the written data matches the expected length and all known hashes.
+    pub fn verify(&mut self, name: &str, meta: &TufMeta) -> Result<(), Error> {
+        if self.length != meta.length {
+            return Err(Error::UptaneMetaLength(format!("{} expected {} bytes, got {}", name, meta.length, self.length)));
+        }
+
+        let mut checked = 0;
+        for (method, expected) in &meta.hashes {
+            let actual = match method.as_ref() {
+                "sha256" => self.sha256.result_str(),
+                "sha512" => self.sha512.result_str(),
+                _ => { trace!("skipping unknown {} hash for {}", method, name); continue }
+            };
+            if actual != expected.to_lowercase() {
+                return Err(Error::UptaneMetaHash(format!("{} {} mismatch: expected {}, got {}", name, method, expected, actual)));
+            }
+            checked += 1;
+        }
+
+        if checked == 0 {
+            Err(Error::UptaneMetaHash(format!("{} has no supported hashes", name)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.length += written as u64;
+        self.sha256.input(&buf[..written]);
+        self.sha512.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct TufCustom {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ecuIdentifier: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardwareIdentifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub targetFormat: Option<TargetFormat>,
 }
 
+impl TufCustom {
+    /// Returns the target's format when set, otherwise `BINARY` for targets
+    /// with a `uri` to download from and `OSTREE` for any others.
+    pub fn target_format(&self) -> TargetFormat {
+        match (self.targetFormat, self.uri.as_ref()) {
+            (Some(format), _) => format,
+            (None, Some(_))   => TargetFormat::Binary,
+            (None, None)      => TargetFormat::Ostree,
+        }
+    }
+}
 
-#[derive(Serialize, PartialEq, Eq, Debug, Clone, Copy, Hash)]
-#[serde(tag = "_type")]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TargetFormat {
+    Binary,
+    Ostree,
+}
+
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
 pub enum RoleName {
     Root,
     Targets,
     Snapshot,
-    Timestamp
+    Timestamp,
+    Delegation(String),
+}
+
+impl RoleName {
+    /// Returns the `_type` expected in the role's metadata.
+    pub fn metadata_type(&self) -> RoleName {
+        match *self {
+            RoleName::Delegation(_) => RoleName::Targets,
+            ref role => role.clone()
+        }
+    }
 }
 
 impl FromStr for RoleName {
@@ -78,6 +201,7 @@ impl Display for RoleName {
             RoleName::Targets   => write!(f, "targets"),
             RoleName::Snapshot  => write!(f, "snapshot"),
             RoleName::Timestamp => write!(f, "timestamp"),
+            RoleName::Delegation(ref name) => write!(f, "{}", name),
         }
     }
 }
@@ -89,6 +213,12 @@ impl<'de> Deserialize<'de> for RoleName {
     }
 }
 
+impl Serialize for RoleName {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(&self.to_string())
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct RoleData {
     pub _type:   RoleName,
@@ -98,11 +228,16 @@ pub struct RoleData {
     pub roles:   Option<HashMap<RoleName, RoleMeta>>, // root only
     pub targets: Option<HashMap<String, TufMeta>>,    // targets only
     pub meta:    Option<HashMap<String, TufMeta>>,    // timestamp/snapshot only
+    pub delegations: Option<Delegations>,             // targets only
 }
 
 impl RoleData {
     pub fn expired(&self) -> bool {
-        self.expires < Utc::now()
+        self.expired_at(Utc::now())
+    }
+
+    pub fn expired_at(&self, time: DateTime<Utc>) -> bool {
+        self.expires < time
     }
 }
 
@@ -114,6 +249,77 @@ pub struct RoleMeta {
     pub version:   u64,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Delegations {
+    pub keys:  HashMap<String, Key>,
+    pub roles: Vec<DelegatedRole>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DelegatedRole {
+    #[serde(deserialize_with = "deserialize_delegated_name")]
+    pub name:      String,
+    pub keyids:    HashSet<String>,
+    pub threshold: u64,
+    pub paths:     Vec<String>,
+    #[serde(default)]
+    pub terminating: bool,
+}
+
+impl DelegatedRole {
+    /// Reject delegated role names that clash with a top-level role or that
+    /// can't be used safely as a metadata file name.
+    pub fn validate_name(name: &str) -> Result<(), Error> {
+        if name.parse::<RoleName>().is_ok() {
+            Err(Error::TufRole(format!("delegated role uses reserved name: {}", name)))
+        } else if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+            Err(Error::TufRole(format!("invalid delegated role name: {}", name)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns whether the target path matches any of the delegated path patterns.
+    pub fn matches(&self, path: &str) -> bool {
+        self.paths.iter().any(|pattern| glob_match(pattern.as_bytes(), path.as_bytes()))
+    }
+}
+
+fn deserialize_delegated_name<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+    let name: String = Deserialize::deserialize(de)?;
+    DelegatedRole::validate_name(&name).map_err(|err| SerdeError::custom(err.to_string()))?;
+    Ok(name)
+}
+
+/// Match a path against a shell-style pattern where `*` matches any characters
+/// and `?` matches any single character. A mismatch only retries from the last
+/// `*`, so remote patterns with many of them can't cause exponential work.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    let mut star = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some(&b'*') => {
+                star = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match star {
+                Some((star_p, star_s)) => {
+                    star = Some((star_p, star_s + 1));
+                    p = star_p + 1;
+                    s = star_s + 1;
+                }
+                None => return false
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Key {
@@ -126,7 +332,7 @@ impl Key {
         let mut hasher = Sha256::new();
         match self.keytype {
             KeyType::Ed25519 => hasher.input_str(&format!(r#""{}""#, self.keyval.public)),
-            KeyType::Rsa => hasher.input(&pem::parse(self.keyval.public.as_bytes())?.contents)
+            KeyType::Ecdsa | KeyType::Rsa => hasher.input(&pem::parse(self.keyval.public.as_bytes())?.contents)
         }
         Ok(hasher.result_str())
     }
@@ -144,6 +350,21 @@ pub struct PrivateKey {
 }
 
 impl PrivateKey {
+    /// Read a PEM or DER encoded private key, with the key id calculated from
+    /// the public key in the same way as `Key::key_id`.
+    pub fn from_paths(private_path: &str, public_path: &str, sig_type: SignatureType) -> Result<Self, Error> {
+        let key = sig_type.public_key(&Util::read_file(public_path)?)?;
+        let der_key = sig_type.private_key(&Util::read_file(private_path)?)?;
+        Ok(PrivateKey { keyid: key.key_id()?, der_key: der_key })
+    }
+
+    /// Wrap a private key returned by `SignatureType::private_key`, deriving the key id
+    /// from its public half.
+    pub fn from_der(der_key: Vec<u8>, sig_type: SignatureType) -> Result<Self, Error> {
+        let key = sig_type.public_from_private(&der_key)?;
+        Ok(PrivateKey { keyid: key.key_id()?, der_key: der_key })
+    }
+
     pub fn sign_data(&self, data: json::Value, sig_type: SignatureType) -> Result<TufSigned, Error> {
         let cjson = CanonicalJson::convert(json::to_value(&data)?)?;
         let signed = TufSigned {
@@ -160,6 +381,8 @@ impl PrivateKey {
 
 #[derive(Serialize, PartialEq, Eq, Debug, Clone)]
 pub enum KeyType {
+    #[serde(rename = "ecdsa-sha2-nistp256")]
+    Ecdsa,
     Ed25519,
     Rsa,
 }
@@ -176,6 +399,7 @@ impl FromStr for KeyType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_ref() {
+            "ecdsa-sha2-nistp256" => Ok(KeyType::Ecdsa),
             "ed25519" => Ok(KeyType::Ed25519),
             "rsa"     => Ok(KeyType::Rsa),
             _         => Err(Error::TufKeyType(s.to_string()))
@@ -184,6 +408,8 @@ impl FromStr for KeyType {
 }
 
 
+const TIMESERVER_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+
 pub type Manifests = HashMap<String, TufSigned>;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -204,6 +430,8 @@ pub struct EcuVersion {
 }
 
 impl EcuVersion {
+    /// Create a new version report, with the time server times left at the
+    /// epoch until they are set from an attested time.
     pub fn from(ecu_serial: String, image: TufImage, custom: Option<EcuCustom>) -> Self {
         EcuVersion {
             attacks_detected: "".into(),
@@ -214,6 +442,12 @@ impl EcuVersion {
             timeserver_time: "1970-01-01T00:00:00Z".into(),
         }
     }
+
+    /// Set the current time server time along with the previously reported time.
+    pub fn set_timeserver_time(&mut self, previous: DateTime<Utc>, current: DateTime<Utc>) {
+        self.previous_timeserver_time = previous.format(TIMESERVER_FORMAT).to_string();
+        self.timeserver_time = current.format(TIMESERVER_FORMAT).to_string();
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -31,8 +31,10 @@ impl Gateway for DBus {
         let arg0 = Argument::new(Some("update_id".into()), Signature::new("s").expect("arg1 signature"));
         let arg1 = arg0.clone();
         let arg2 = Argument::new(Some("operations_results".into()), Signature::new("aa{sv}").expect("arg2 signature"));
+        let arg3 = Argument::new(Some("path".into()), Signature::new("s").expect("arg3 signature"));
         let ctx1 = ctx.clone();
         let ctx2 = ctx.clone();
+        let ctx3 = ctx.clone();
 
         let fact = Factory::new_fn::<()>();
         let tree = fact.tree(()).add(
@@ -52,7 +54,14 @@ impl Gateway for DBus {
                         let report = InstallReport::new(id, res);
                         ctx2.send(CommandExec { cmd: Command::SendInstallReport(report), etx: None });
                         Ok(Vec::new())
-                    }).in_arg(arg1).in_arg(arg2))));
+                    }).in_arg(arg1).in_arg(arg2))
+
+                    .add_m(fact.method("installBundle", (), move |info| {
+                        debug!("dbus installBundle called: {:?}", info);
+                        let path: &str = info.msg.read1()?;
+                        ctx3.send(CommandExec { cmd: Command::InstallBundle(path.into()), etx: None });
+                        Ok(Vec::new())
+                    }).in_arg(arg3))));
 
         let session_cfg = self.cfg.clone();
         let session_ctx = ctx.clone();
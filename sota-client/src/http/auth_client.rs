@@ -2,19 +2,23 @@ use chan::Sender;
 use hyper::client::{Body, Client as HyperClient, ProxyConfig, RedirectPolicy,
                     Response as HyperResponse};
 use hyper::header::{Authorization, Basic, Bearer, Connection, ContentLength,
-                    ContentType, Headers, Location, UserAgent};
+                    ContentType, ETag, Headers, IfModifiedSince, IfNoneMatch,
+                    LastModified, Location, UserAgent};
 use hyper::mime::{Attr, Mime, TopLevel, SubLevel, Value};
 use hyper::net::{HttpConnector, HttpsConnector};
 use hyper::status::StatusCode;
 use std::{env, str};
-use std::io::Read;
+use std::io::{self, Read, Write};
 use time;
+use url;
 
-use datatype::{Auth, Error};
-use http::{Client, Request, Response, ResponseData, TlsClient};
-use url::Url;
+use datatype::{Auth, Error, Method, Url};
+use http::{CacheValidators, Client, Request, Response, ResponseData, TlsClient};
 
 
+/// Only the start of an error response body is kept, however long it is.
+const MAX_ERROR_BYTES: u64 = 16 * 1024;
+
 /// The `AuthClient` will attach an `Authentication` header to each outgoing request.
 pub struct AuthClient {
     auth: Auth,
@@ -32,6 +36,14 @@ impl Client for AuthClient {
     fn chan_request(&self, req: Request, resp_tx: Sender<Response>) {
         resp_tx.send(self.send(AuthRequest::new(&self.auth, req)));
     }
+
+    fn get_into(&self, url: Url, limit: u64, sink: &mut Write) -> Result<(), Error> {
+        self.stream(url, limit, None, sink).map(|_| ())
+    }
+
+    fn get_modified(&self, url: Url, limit: u64, cache: &mut CacheValidators, sink: &mut Write) -> Result<bool, Error> {
+        self.stream(url, limit, Some(cache), sink)
+    }
 }
 
 impl AuthClient {
@@ -39,7 +51,7 @@ impl AuthClient {
     pub fn from(auth: Auth, version: Option<String>) -> Self {
         let mut client = env::var("HTTP_PROXY").map(|ref proxy| {
             let tls = TlsClient::default();
-            let url = Url::parse(proxy).expect("couldn't parse HTTP_PROXY");
+            let url = url::Url::parse(proxy).expect("couldn't parse HTTP_PROXY");
             let host = url.host_str().expect("couldn't parse HTTP_PROXY host").to_string();
             let port = url.port_or_known_default().expect("couldn't parse HTTP_PROXY port");
             let proxy = ProxyConfig::new(url.scheme(), host, port, HttpConnector::default(), tls);
@@ -51,6 +63,87 @@ impl AuthClient {
     }
 
     fn send(&self, req: AuthRequest) -> Response {
+        let mut resp = match self.execute(&req) {
+            Ok(resp) => resp,
+            Err(err) => return Response::Error(Box::new(err))
+        };
+
+        let mut body = Vec::new();
+        let data = match resp.read_to_end(&mut body) {
+            Ok(_) => ResponseData { code: resp.status, body: body },
+            Err(err) => {
+                let msg = format!("couldn't read response body: {}", err);
+                return Response::Error(Box::new(Error::Client(msg)));
+            }
+        };
+        debug!("response body size: {}", data.body.len());
+
+        if resp.status.is_redirection() {
+            match self.redirect(&req, &resp) {
+                Ok(next) => self.send(next),
+                Err(err) => Response::Error(Box::new(err))
+            }
+        } else if resp.status.is_success() {
+            Response::Success(data)
+        } else if resp.status == StatusCode::Unauthorized || resp.status == StatusCode::Forbidden {
+            Response::Error(Box::new(Error::HttpAuth(data)))
+        } else {
+            Response::Failed(data)
+        }
+    }
+
+    /// Stream a GET response body into `sink`, sending and updating any cache
+    /// validators, and returning `false` if the resource was not modified.
+    fn stream(&self, url: Url, limit: u64, mut cache: Option<&mut CacheValidators>, sink: &mut Write) -> Result<bool, Error> {
+        info!("GET {} (streamed)", url);
+        let mut req = AuthRequest::new(&self.auth, Request { method: Method::Get, url: url, body: None });
+        loop {
+            if let Some(ref cache) = cache {
+                if let Some(ref etag) = cache.etag {
+                    req.headers.set(IfNoneMatch::Items(vec![etag.clone()]));
+                }
+                if let Some(date) = cache.last_modified {
+                    req.headers.set(IfModifiedSince(date));
+                }
+            }
+
+            let mut resp = self.execute(&req)?;
+            if resp.status == StatusCode::NotModified {
+                return Ok(false);
+            } else if resp.status.is_redirection() {
+                req = self.redirect(&req, &resp)?;
+                continue;
+            } else if !resp.status.is_success() {
+                let mut body = Vec::new();
+                (&mut resp).take(MAX_ERROR_BYTES).read_to_end(&mut body).map_err(|err| Error::Client(format!("couldn't read response body: {}", err)))?;
+                let data = ResponseData { code: resp.status, body: body };
+                return if resp.status == StatusCode::Unauthorized || resp.status == StatusCode::Forbidden {
+                    Err(Error::HttpAuth(data))
+                } else {
+                    Err(Error::Http(data))
+                };
+            }
+
+            if resp.headers.get::<ContentLength>().map_or(false, |len| len.0 > limit) {
+                return Err(Error::HttpLimit(limit));
+            }
+            let written = io::copy(&mut (&mut resp).take(limit), sink)?;
+            debug!("response body size: {}", written);
+            let mut extra = [0; 1];
+            if resp.read(&mut extra)? > 0 {
+                return Err(Error::HttpLimit(limit));
+            }
+
+            if let Some(ref mut cache) = cache {
+                cache.etag = resp.headers.get::<ETag>().map(|etag| etag.0.clone());
+                cache.last_modified = resp.headers.get::<LastModified>().map(|date| date.0);
+            }
+            return Ok(true);
+        }
+    }
+
+    /// Send the request and return the response before its body is read.
+    fn execute(&self, req: &AuthRequest) -> Result<HyperResponse, Error> {
         let started = time::precise_time_ns();
         let mut headers = req.headers.clone();
         if let Some(ref version) = self.version {
@@ -68,59 +161,28 @@ impl AuthClient {
             }
         }
 
-        match request.send() {
-            Ok(mut resp) => {
-                info!("Response status: {}", resp.status);
-                debug!("response headers:\n{}", resp.headers);
-                let latency = time::precise_time_ns() as f64 - started as f64;
-                debug!("response latency: {}ms", (latency / 1e6) as u32);
-
-                let mut body = Vec::new();
-                let data = match resp.read_to_end(&mut body) {
-                    Ok(_) => ResponseData { code: resp.status, body: body },
-                    Err(err) => {
-                        let msg = format!("couldn't read response body: {}", err);
-                        return Response::Error(Box::new(Error::Client(msg)));
-                    }
-                };
-                debug!("response body size: {}", data.body.len());
-
-                if resp.status.is_redirection() {
-                    self.redirect_request(&req, resp)
-                } else if resp.status.is_success() {
-                    Response::Success(data)
-                } else if resp.status == StatusCode::Unauthorized || resp.status == StatusCode::Forbidden {
-                    Response::Error(Box::new(Error::HttpAuth(data)))
-                } else {
-                    Response::Failed(data)
-                }
-            }
-
-            Err(err) => Response::Error(Box::new(Error::Client(format!("couldn't send request: {}", err))))
-        }
+        let resp = request.send().map_err(|err| Error::Client(format!("couldn't send request: {}", err)))?;
+        info!("Response status: {}", resp.status);
+        debug!("response headers:\n{}", resp.headers);
+        let latency = time::precise_time_ns() as f64 - started as f64;
+        debug!("response latency: {}ms", (latency / 1e6) as u32);
+        Ok(resp)
     }
 
     /// Redirect drops the Authorization header.
-    fn redirect_request(&self, req: &AuthRequest, resp: HyperResponse) -> Response {
-        resp.headers
-            .get::<Location>()
-            .map(|loc| {
-                self.send(AuthRequest::new(&Auth::None, Request {
-                    url: match loc.parse() {
-                        Ok(absolute) => absolute,
-                        Err(_) if loc[0..1] == *"/" => req.request.url.join(loc), // relative
-                        Err(err) => {
-                            let msg = format!("`{}` not a url: {}", loc, err);
-                            return Response::Error(Box::new(Error::Parse(msg)))
-                        }
-                    },
-                    method: req.request.method.clone(),
-                    body:   req.request.body.clone(),
-                }))
-            })
-            .unwrap_or_else(|| {
-                Response::Error(Box::new(Error::Client("redirect missing Location header".into())))
-            })
+    fn redirect(&self, req: &AuthRequest, resp: &HyperResponse) -> Result<AuthRequest, Error> {
+        let loc = resp.headers.get::<Location>()
+            .ok_or_else(|| Error::Client("redirect missing Location header".into()))?;
+        let url = match loc.parse() {
+            Ok(absolute) => absolute,
+            Err(_) if loc[0..1] == *"/" => req.request.url.join(loc), // relative
+            Err(err) => return Err(Error::Parse(format!("`{}` not a url: {}", loc, err)))
+        };
+        Ok(AuthRequest::new(&Auth::None, Request {
+            url:    url,
+            method: req.request.method.clone(),
+            body:   req.request.body.clone(),
+        }))
     }
 }
 
@@ -173,6 +235,8 @@ impl AuthRequest {
 mod tests {
     use super::*;
     use json;
+    use std::net::TcpListener;
+    use std::thread;
 
     use http::{Client, Response, TlsClient, TlsData};
 
@@ -195,6 +259,34 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_get_into_limit() {
+        let url: Url = "http://eu.httpbin.org/bytes/16?seed=123".parse().unwrap();
+        let mut body = Vec::new();
+        get_client().get_into(url.clone(), 16, &mut body).expect("get_into");
+        assert_eq!(body, vec![13, 22, 104, 27, 230, 9, 137, 85, 218, 40, 86, 85, 62, 0, 111, 22]);
+        match get_client().get_into(url, 15, &mut Vec::new()) {
+            Err(Error::HttpLimit(15)) => (),
+            other => panic!("expected limit error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_error_body_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}/timestamp.json", listener.local_addr().expect("addr")).parse().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let _ = stream.read(&mut [0; 4096]);
+            let _ = stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 1048576\r\n\r\n");
+            let _ = stream.write_all(&vec![b'x'; 1024 * 1024]);
+        });
+        match get_client().get_into(url, 16, &mut Vec::new()) {
+            Err(Error::Http(data)) => assert_eq!(data.body.len() as u64, MAX_ERROR_BYTES),
+            other => panic!("expected server error, got {:?}", other)
+        }
+    }
+
     #[test]
     fn test_send_post_request() {
         let url = "https://eu.httpbin.org/post".parse().unwrap();
@@ -1,6 +1,8 @@
 use chan::{self, Sender, Receiver};
+use hyper::header::{EntityTag, HttpDate};
 use hyper::status::StatusCode;
 use std::fmt::{self, Display, Formatter};
+use std::io::Write;
 use std::str;
 
 use datatype::{Error, Method, Url};
@@ -33,6 +35,24 @@ pub trait Client: Send {
         self.send_request(Request { method, url, body })
     }
 
+    /// Send a GET request and stream a successful response body into `sink`,
+    /// failing once more than `limit` bytes have been received.
+    fn get_into(&self, url: Url, limit: u64, sink: &mut Write) -> Result<(), Error> {
+        match self.get(url, None).recv().expect("couldn't receive response") {
+            Response::Success(ref data) if data.body.len() as u64 > limit => Err(Error::HttpLimit(limit)),
+            Response::Success(data) => Ok(sink.write_all(&data.body)?),
+            Response::Failed(data)  => Err(Error::Http(data)),
+            Response::Error(err)    => Err(*err)
+        }
+    }
+
+    /// Send a conditional GET request using any cached validators, returning
+    /// `false` without writing to `sink` if the resource was not modified.
+    /// Clients without support for conditional requests always fetch the body.
+    fn get_modified(&self, url: Url, limit: u64, _cache: &mut CacheValidators, sink: &mut Write) -> Result<bool, Error> {
+        self.get_into(url, limit, sink).map(|_| true)
+    }
+
     fn is_testing(&self) -> bool { false }
 }
 
@@ -46,6 +66,14 @@ pub struct Request {
 }
 
 
+/// The validators of a previous response used to send a conditional request.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheValidators {
+    pub etag:          Option<EntityTag>,
+    pub last_modified: Option<HttpDate>,
+}
+
+
 /// A Response enumerates between a successful (e.g. 2xx) HTTP response, a failed
 /// (e.g. 4xx/5xx) response, or an Error before receiving any response.
 #[derive(Debug)]
@@ -4,6 +4,6 @@ pub mod test_client;
 pub mod tls;
 
 pub use self::auth_client::AuthClient;
-pub use self::http_client::{Client, Request, Response, ResponseData};
+pub use self::http_client::{CacheValidators, Client, Request, Response, ResponseData};
 pub use self::test_client::TestClient;
 pub use self::tls::{Pkcs12, TlsClient, TlsData};
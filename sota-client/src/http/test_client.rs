@@ -7,34 +7,50 @@ use datatype::{Error, Util};
 use http::{Client, Request, Response, ResponseData};
 
 
-/// The `TestClient` will return an ordered list of successful HTTP responses.
+/// The `TestClient` will return an ordered list of HTTP responses.
 #[derive(Default)]
 pub struct TestClient {
-    responses: RefCell<VecDeque<Vec<u8>>>
+    responses: RefCell<VecDeque<ResponseData>>
 }
 
 impl TestClient {
-    /// Create a new `TestClient` that will return these responses.
+    /// Create a new `TestClient` that will return these successful responses.
     pub fn from(responses: Vec<Vec<u8>>) -> TestClient {
+        let data = responses.into_iter()
+            .map(|body| ResponseData { code: StatusCode::Ok, body: body })
+            .collect();
+        TestClient::from_data(data)
+    }
+
+    /// Create a new `TestClient` that will return these responses, failing on
+    /// any non-successful status code.
+    pub fn from_data(responses: Vec<ResponseData>) -> TestClient {
         TestClient { responses: RefCell::new(VecDeque::from(responses)) }
     }
 
     /// Create a new `TestClient` that will return each file's data as a response.
     pub fn from_paths(reply_paths: &[&str]) -> TestClient {
-        let responses = reply_paths.iter()
-            .map(|path| Util::read_file(path).expect(&format!("couldn't read {}", path)))
-            .collect();
-        TestClient::from(responses)
+        TestClient::from(reply_paths.iter().map(|path| read_fixture(path)).collect())
     }
 }
 
+/// Read a test fixture without the trailing newline that a server wouldn't send.
+pub fn read_fixture(path: &str) -> Vec<u8> {
+    let mut data = Util::read_file(path).expect(&format!("couldn't read {}", path));
+    if data.last() == Some(&b'\n') { let _ = data.pop(); }
+    data
+}
+
 impl Client for TestClient {
     fn chan_request(&self, req: Request, resp_tx: Sender<Response>) {
         self.responses
             .borrow_mut()
             .pop_front()
-            .map(|body| ResponseData { code: StatusCode::Ok, body: body })
-            .map(|data| resp_tx.send(Response::Success(data)))
+            .map(|data| if data.code.is_success() {
+                resp_tx.send(Response::Success(data))
+            } else {
+                resp_tx.send(Response::Failed(data))
+            })
             .unwrap_or_else(|| {
                 resp_tx.send(Response::Error(Box::new(Error::Client(req.url.to_string()))))
             })
@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc};
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::Write;
 use std::path::Path;
@@ -13,7 +13,8 @@ use datatype::{Error, Util};
 
 
 const CHUNK_DIR: &'static str = "/tmp/sota-image-chunks";
-const CHUNK_SIZE: usize = 64*1024;
+/// Size of each image chunk sent between a `Primary` and `Secondary`.
+pub const CHUNK_SIZE: usize = 64*1024;
 
 
 struct Chunk([u8; CHUNK_SIZE]);
@@ -109,7 +110,10 @@ pub struct ImageWriter {
     pub image_dir: String,
     pub last_written: DateTime<Utc>,
     pub chunks_written: HashSet<u64>,
-    pub chunks_available: HashSet<u64>,
+    pub chunks_available: BTreeSet<u64>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub chunks_requested: HashSet<u64>,
 }
 
 impl ImageWriter {
@@ -122,6 +126,7 @@ impl ImageWriter {
             last_written: Utc::now(),
             chunks_written: HashSet::new(),
             chunks_available: chunks,
+            chunks_requested: HashSet::new(),
         }
     }
 
@@ -134,6 +139,7 @@ impl ImageWriter {
         Util::write_file(&chunk_path, data)?;
         self.chunks_written.insert(index);
         self.chunks_available.remove(&index);
+        self.chunks_requested.remove(&index);
         self.last_written = Utc::now();
         Ok(())
     }
@@ -193,6 +199,7 @@ impl ImageWriter {
         file.flush()?;
         self.chunks_written.insert(index);
         self.chunks_available.remove(&index);
+        self.chunks_requested.remove(&index);
         self.last_written = Utc::now();
         Ok(())
     }
@@ -213,6 +220,19 @@ impl ImageWriter {
         self.chunks_available.iter().next().cloned()
     }
 
+    /// Mark the next unwritten chunks as requested so that no more than
+    /// `window` requests are outstanding, returning their indices.
+    pub fn next_chunks(&mut self, window: usize) -> Vec<u64> {
+        let wanted = window.saturating_sub(self.chunks_requested.len());
+        let next = self.chunks_available.iter()
+            .filter(|index| ! self.chunks_requested.contains(index))
+            .take(wanted)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.chunks_requested.extend(&next);
+        next
+    }
+
     /// Verify the output image checksum.
     pub fn verify_image(&self) -> Result<(), Error> {
         let mut reader = ImageReader::new(self.meta.image_name.clone(), self.image_dir.clone())?;
@@ -312,4 +332,16 @@ mod test {
         let written = Util::read_file(&format!("{}/{}", dir, outfile)).expect("written");
         assert_eq!(&written[..], &buf[..]);
     }
+
+    #[test]
+    fn image_writer_window() {
+        let meta = ImageMeta::new("test-image-window.dat".into(), 5 * CHUNK_SIZE as u64, 5, "".into());
+        let mut writer = ImageWriter::new(meta, format!("/tmp/sota-test-image-{}", Utc::now().timestamp()));
+        assert_eq!(writer.next_chunks(3), vec![0, 1, 2]);
+        assert_eq!(writer.next_chunks(3), Vec::<u64>::new());
+        writer.write_direct(&[0; CHUNK_SIZE], 1).expect("write chunk");
+        assert_eq!(writer.next_chunks(3), vec![3]);
+        writer.chunks_requested.clear();
+        assert_eq!(writer.next_chunks(3), vec![0, 2, 3]);
+    }
 }
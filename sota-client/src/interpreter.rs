@@ -1,17 +1,19 @@
 use chan::{Sender, Receiver};
+use json;
 use std::cell::RefCell;
 use std::process::{self, Command as ShellCommand};
 use std::rc::Rc;
 
 use authenticate::oauth2;
+use bundle::Bundle;
 use datatype::{Auth, Command, Config, EcuCustom, Error, Event, InstallCode,
-               InstallOutcome, InstallResult, RoleName, RequestStatus, Url};
+               InstallOutcome, InstallResult, Manifests, RequestStatus, Url};
 use http::{AuthClient, Client};
 use pacman::{Credentials, PacMan};
 #[cfg(feature = "rvi")]
 use rvi::Services;
 use sota::Sota;
-use uptane::Uptane;
+use uptane::{self, Uptane};
 
 
 /// An `Interpreter` loops over any incoming values, on receipt of which it
@@ -46,7 +48,7 @@ impl Interpreter<Event, CommandExec> for EventInterpreter {
             Event::Authenticated if self.initial => {
                 self.loop_tx.send(Event::InstalledPackagesNeeded);
                 self.loop_tx.send(Event::SystemInfoNeeded);
-                self.loop_tx.send(Event::UptaneManifestNeeded);
+                if self.pacman == PacMan::Uptane { queue(Command::UptaneResumeInstall) }
                 self.initial = false;
             }
 
@@ -179,12 +181,14 @@ impl CommandInterpreter {
 
             (Command::GetUpdateRequests, CommandMode::Uptane(uptane)) => {
                 let mut uptane = uptane.borrow_mut();
-                let _ = uptane.get_director(&*self.http, RoleName::Root)?;
-                let targets = uptane.get_director(&*self.http, RoleName::Targets)?;
-                if targets.is_new() {
-                    Event::UptaneTargetsUpdated(Box::new(targets))
-                } else {
-                    Event::UptaneNoUpdates
+                if uptane.time_server.is_some() {
+                    if let Err(err) = uptane.update_time(&*self.http) {
+                        error!("Couldn't update the trusted time: {}", err);
+                    }
+                }
+                match uptane.poll_targets(&*self.http)? {
+                    Some(targets) => Event::UptaneTargetsUpdated(Box::new(targets)),
+                    None => Event::UptaneNoUpdates
                 }
             }
 
@@ -199,6 +203,21 @@ impl CommandInterpreter {
                 }
             }
 
+            (Command::InstallBundle(path), CommandMode::Uptane(uptane)) => {
+                let mut uptane = uptane.borrow_mut();
+                let bundle = Bundle::open(&path)?;
+                let result = uptane.install_bundle(&bundle, self.credentials());
+                let (manifests, is_success) = self.uptane_outcome(&mut uptane, result)?;
+                let (manifest, _) = uptane.signed_manifest(Some(manifests.clone()))?;
+                bundle.write_manifest(&json::to_vec(&manifest)?)?;
+                etx.send(if is_success { Event::UptaneInstallComplete(manifests) } else { Event::UptaneInstallFailed(manifests) });
+                Event::BundleInstalled(path)
+            }
+
+            (Command::InstallBundle(path), _) => {
+                return Err(Error::Bundle(format!("{} can only be verified and installed in Uptane mode", path)));
+            }
+
             (Command::ListInstalledPackages, _) => {
                 Event::FoundInstalledPackages(self.config.device.package_manager.installed_packages()?)
             }
@@ -267,6 +286,19 @@ impl CommandInterpreter {
 
             (Command::Shutdown, _) => process::exit(0),
 
+            (Command::UptaneResumeInstall, CommandMode::Uptane(uptane)) => {
+                let mut uptane = uptane.borrow_mut();
+                if uptane.is_interrupted() {
+                    let result = uptane.resume(self.credentials());
+                    match self.uptane_outcome(&mut uptane, result)? {
+                        (manifests, true)  => Event::UptaneInstallComplete(manifests),
+                        (manifests, false) => Event::UptaneInstallFailed(manifests),
+                    }
+                } else {
+                    Event::UptaneManifestNeeded
+                }
+            }
+
             (Command::UptaneSendManifest(manifests), CommandMode::Uptane(uptane)) => {
                 let mut uptane = uptane.borrow_mut();
                 uptane.put_manifest(&*self.http, manifests)?;
@@ -275,20 +307,16 @@ impl CommandInterpreter {
 
             (Command::UptaneStartInstall(targets), CommandMode::Uptane(uptane)) => {
                 let mut uptane = uptane.borrow_mut();
-                match uptane.install(*targets, self.treehub()?, self.credentials()) {
-                    Ok((signed, true))  => Event::UptaneInstallComplete(signed),
-                    Ok((signed, false)) => Event::UptaneInstallFailed(signed),
-                    Err(err) => {
-                        error!("Uptane installation error: {}", err);
-                        let result = InstallOutcome::error(err.to_string()).into_result(uptane.primary_ecu.clone());
-                        let report = uptane.signed_report(Some(EcuCustom::from_result(result)))?;
-                        Event::UptaneInstallFailed(hashmap!{ uptane.primary_ecu.clone() => report })
-                    }
+                let result = uptane.install(*targets, self.treehub()?, self.credentials());
+                match self.uptane_outcome(&mut uptane, result)? {
+                    (manifests, true)  => Event::UptaneInstallComplete(manifests),
+                    (manifests, false) => Event::UptaneInstallFailed(manifests),
                 }
             }
 
             (Command::SendInstalledSoftware(_), _) => unreachable!("Command::SendInstalledSoftware expects CommandMode::Rvi"),
             (Command::StartInstall(_), _)          => unreachable!("Command::StartInstall expects CommandMode::Sota"),
+            (Command::UptaneResumeInstall, _)      => unreachable!("Command::UptaneResumeInstall expects CommandMode::Uptane"),
             (Command::UptaneSendManifest(_), _)    => unreachable!("Command::UptaneSendManifest expects CommandMode::Uptane"),
             (Command::UptaneStartInstall(_), _)    => unreachable!("Command::UptaneStartInstall expects CommandMode::Uptane"),
         };
@@ -296,6 +324,20 @@ impl CommandInterpreter {
         Ok(event)
     }
 
+    /// Convert an Uptane installation error into a failure report from the primary.
+    fn uptane_outcome(&self, uptane: &mut Uptane, result: Result<(Manifests, bool), Error>) -> Result<(Manifests, bool), Error> {
+        match result {
+            Ok(outcome) => Ok(outcome),
+            Err(err) => {
+                error!("Uptane installation error: {}", err);
+                let outcome = InstallOutcome::new(uptane::install_code(&err), "".into(), err.to_string());
+                let result = outcome.into_result(uptane.primary_ecu.clone());
+                let report = uptane.signed_report(Some(EcuCustom::from_result(result)))?;
+                Ok((hashmap!{ uptane.primary_ecu.clone() => report }, false))
+            }
+        }
+    }
+
     /// Generate a new system information report.
     fn system_info(&self) -> Result<String, Error> {
         let cmd = self.config.device.system_info.as_ref()
@@ -336,13 +378,15 @@ mod tests {
     use super::*;
 
     use chan::{self, Sender, Receiver};
-    use std::thread;
     use std::fmt::Debug;
+    use std::path::Path;
+    use std::thread;
     use uuid::Uuid;
 
-    use datatype::{Auth, Command, Config, DownloadComplete, Event, InstallCode};
+    use datatype::{Auth, Command, Config, DownloadComplete, Event, InstallCode, Util};
     use http::TestClient;
     use pacman::PacMan;
+    use pacman::test::TestDir;
 
 
     fn new_interpreter(replies: Vec<Vec<u8>>, succeeds: bool) -> (Sender<Command>, Receiver<Event>) {
@@ -410,4 +454,16 @@ mod tests {
             Event::InstallFailed(new_result(InstallCode::INSTALL_FAILED)),
         ]);
     }
+
+    #[test]
+    fn install_bundle_requires_uptane() {
+        let dir = TestDir::new("sota-interpreter-bundle");
+        Util::write_file(&format!("{}/packages/tampered.deb", dir.0), b"tampered").expect("write package");
+        let (ctx, erx) = new_interpreter(Vec::new(), true);
+        ctx.send(Command::InstallBundle(dir.0.clone()));
+        assert_rx(&erx, &[
+            Event::Error(format!("Bundle error: {} can only be verified and installed in Uptane mode", dir.0)),
+        ]);
+        assert!(!Path::new(&format!("{}/manifest.json", dir.0)).exists());
+    }
 }
@@ -42,6 +42,7 @@ extern crate uuid;
 pub mod atomic;
 pub mod authenticate;
 pub mod broadcast;
+pub mod bundle;
 pub mod datatype;
 pub mod gateway;
 pub mod http;
@@ -51,4 +52,5 @@ pub mod pacman;
 #[cfg(feature = "rvi")]
 pub mod rvi;
 pub mod sota;
+pub mod transport;
 pub mod uptane;
@@ -14,7 +14,7 @@ use chan_signal::Signal;
 use env_logger::LogBuilder;
 use getopts::Options;
 use log::LogLevelFilter;
-use std::{env, process, thread};
+use std::{env, iter, process, thread};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::Duration;
@@ -233,6 +233,7 @@ fn build_config(version: &Option<String>) -> Config {
     opts.optopt("", "device-system-info", "change the system information command", "PATH");
 
     opts.optmulti("", "ecu-serial", "add a secondary ECU serial", "SERIAL");
+    opts.optmulti("", "ecu-hardware-id", "add a secondary ECU hardware identifier", "TEXT");
     opts.optmulti("", "ecu-public-key-path", "add a secondary ECU public key path", "PATH");
     opts.optmulti("", "ecu-manifest-path", "add a secondary ECU manifest path", "PATH");
 
@@ -261,11 +262,19 @@ fn build_config(version: &Option<String>) -> Config {
     opts.optopt("", "uptane-director-server", "change the Uptane Director server", "URL");
     opts.optopt("", "uptane-repo-server", "change the Uptane Repo server", "URL");
     opts.optopt("", "uptane-primary-ecu-serial", "change the primary ECU's serial", "TEXT");
+    opts.optopt("", "uptane-hardware-id", "change the primary ECU's hardware identifier", "TEXT");
     opts.optopt("", "uptane-metadata-path", "change the directory used to save Uptane metadata.", "PATH");
     opts.optopt("", "uptane-private-key-path", "change the path to the private key for the primary ECU", "PATH");
     opts.optopt("", "uptane-public-key-path", "change the path to the public key for the primary ECU", "PATH");
+    opts.optopt("", "uptane-signature-type", "change the signature type used by the primary ECU", "TYPE");
+    opts.optopt("", "uptane-atomic-transport", "change the atomic transaction transport", "TYPE");
     opts.optopt("", "uptane-atomic-primary", "change the atomic transaction Primary server", "IP:PORT");
+    opts.optopt("", "uptane-atomic-socket", "change the atomic transaction unix socket path", "PATH");
+    opts.optopt("", "uptane-atomic-can-interface", "change the atomic transaction CAN interface", "NAME");
     opts.optopt("", "uptane-atomic-timeout-sec", "change the atomic update timeout duration", "SEC");
+    opts.optopt("", "uptane-atomic-recover-path", "change the atomic transaction recovery file", "PATH");
+    opts.optopt("", "uptane-max-root-size", "change the maximum size of downloaded root metadata", "BYTES");
+    opts.optopt("", "uptane-max-timestamp-size", "change the maximum size of downloaded timestamp metadata", "BYTES");
 
     let cli = opts.parse(&args[1..]).expect("couldn't parse args");
     if cli.opt_present("help") {
@@ -303,15 +312,17 @@ fn build_config(version: &Option<String>) -> Config {
     cli.opt_str("device-system-info").map(|cmd| config.device.system_info = Some(cmd));
 
     let ecu_serials = cli.opt_strs("ecu-serial");
+    let ecu_hardware = cli.opt_strs("ecu-hardware-id");
     let ecu_keys = cli.opt_strs("ecu-public-key-path");
     let ecu_manifests = cli.opt_strs("ecu-manifest-path");
-    match (ecu_serials.len(), ecu_keys.len(), ecu_manifests.len()) {
-        (0, 0, 0) => (),
-        (a, b, c) if a == b && a == c => {
+    match (ecu_serials.len(), ecu_hardware.len(), ecu_keys.len(), ecu_manifests.len()) {
+        (0, 0, 0, 0) => (),
+        (a, b, c, d) if (a == b || b == 0) && a == c && a == d => {
             config.ecus = ecu_serials.into_iter()
+                .zip(ecu_hardware.into_iter().map(Some).chain(iter::repeat(None)))
                 .zip(ecu_keys)
                 .zip(ecu_manifests)
-                .map(|((s, p), m)| EcuConfig { ecu_serial: s, public_key_path: p, manifest_path: m })
+                .map(|(((s, h), p), m)| EcuConfig { ecu_serial: s, hardware_id: h, public_key_path: p, manifest_path: m, ..EcuConfig::default() })
                 .collect::<Vec<EcuConfig>>();
         }
         _ => exit!(1, "equal number of 'ecu-' flags expected")
@@ -344,11 +355,19 @@ fn build_config(version: &Option<String>) -> Config {
     cli.opt_str("uptane-director-server").map(|text| config.uptane.director_server = text.parse().expect("Invalid uptane-director-server URL"));
     cli.opt_str("uptane-repo-server").map(|text| config.uptane.repo_server = text.parse().expect("Invalid uptane-repo-server URL"));
     cli.opt_str("uptane-primary-ecu-serial").map(|text| config.uptane.primary_ecu_serial = text);
+    cli.opt_str("uptane-hardware-id").map(|text| config.uptane.hardware_id = Some(text));
     cli.opt_str("uptane-metadata-path").map(|text| config.uptane.metadata_path = text);
     cli.opt_str("uptane-private-key-path").map(|text| config.uptane.private_key_path = text);
     cli.opt_str("uptane-public-key-path").map(|text| config.uptane.public_key_path = text);
+    cli.opt_str("uptane-signature-type").map(|text| config.uptane.signature_type = text.parse().expect("Invalid uptane-signature-type"));
+    cli.opt_str("uptane-atomic-transport").map(|kind| config.uptane.atomic_transport = kind.parse().expect("Invalid uptane-atomic-transport"));
     cli.opt_str("uptane-atomic-primary").map(|addr| config.uptane.atomic_primary = addr.parse().expect("Invalid uptane-atomic-primary"));
+    cli.opt_str("uptane-atomic-socket").map(|path| config.uptane.atomic_socket = path);
+    cli.opt_str("uptane-atomic-can-interface").map(|name| config.uptane.atomic_can_interface = name);
     cli.opt_str("uptane-atomic-timeout-sec").map(|sec| config.uptane.atomic_timeout_sec = sec.parse().expect("Invalid uptane-atomic-timeout-sec"));
+    cli.opt_str("uptane-atomic-recover-path").map(|path| config.uptane.atomic_recover_path = Some(path));
+    cli.opt_str("uptane-max-root-size").map(|size| config.uptane.max_root_size = size.parse().expect("Invalid uptane-max-root-size"));
+    cli.opt_str("uptane-max-timestamp-size").map(|size| config.uptane.max_timestamp_size = size.parse().expect("Invalid uptane-max-timestamp-size"));
 
     if cli.opt_present("print") {
         exit!(0, "{:#?}", config);
@@ -0,0 +1,456 @@
+use libc::{self, c_int, c_void, sa_family_t, sockaddr, socklen_t, timeval};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as SerdeError;
+use std::cmp;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::RawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use datatype::Error;
+
+
+/// Protocol number of ISO-TP sockets in the CAN protocol family.
+const CAN_ISOTP: c_int = 6;
+/// Flag marking a 29-bit extended CAN identifier.
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+/// Largest 11-bit standard CAN identifier.
+const CAN_SFF_MASK: u32 = 0x7ff;
+/// Largest PDU of classic ISO-TP, so larger writes are split across PDUs.
+const ISOTP_MAX_PDU: usize = 4095;
+
+
+/// A connected byte stream between a primary and one of its secondaries.
+pub trait Transport: Read + Write + Send {
+    /// Return another handle to the same stream so it can be written from another thread.
+    fn try_clone(&self) -> Result<Box<Transport>, Error>;
+    /// Set the timeout for blocking reads and writes.
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error>;
+}
+
+/// Waits for secondaries to connect to a primary.
+pub trait Listener: Send {
+    /// Block until the next secondary connects. Returns `Error::Recv` once the
+    /// listener is closed and no more connections will arrive.
+    fn accept(&mut self) -> Result<Box<Transport>, Error>;
+}
+
+
+/// The available transports for the Primary/Secondary bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportType {
+    Tcp,
+    Unix,
+    IsoTp,
+}
+
+impl FromStr for TransportType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "tcp"   => Ok(TransportType::Tcp),
+            "unix"  => Ok(TransportType::Unix),
+            "isotp" => Ok(TransportType::IsoTp),
+            _ => Err(Error::Parse(format!("unknown transport: {}", s)))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TransportType {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let s: String = Deserialize::deserialize(de)?;
+        s.parse().map_err(|err| SerdeError::custom(format!("{}", err)))
+    }
+}
+
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> Result<Box<Transport>, Error> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.set_read_timeout(Some(timeout))?;
+        Ok(self.set_write_timeout(Some(timeout))?)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&mut self) -> Result<Box<Transport>, Error> {
+        let (stream, _) = TcpListener::accept(self)?;
+        Ok(Box::new(stream))
+    }
+}
+
+
+impl Transport for UnixStream {
+    fn try_clone(&self) -> Result<Box<Transport>, Error> {
+        Ok(Box::new(UnixStream::try_clone(self)?))
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.set_read_timeout(Some(timeout))?;
+        Ok(self.set_write_timeout(Some(timeout))?)
+    }
+}
+
+impl Listener for UnixListener {
+    fn accept(&mut self) -> Result<Box<Transport>, Error> {
+        let (stream, _) = UnixListener::accept(self)?;
+        Ok(Box::new(stream))
+    }
+}
+
+
+/// Create a transport between threads of the same process, such as for the
+/// primary's own installer or for tests.
+pub fn channel() -> (ChannelListener, ChannelConnector) {
+    let (tx, rx) = mpsc::channel();
+    (ChannelListener { rx: rx }, ChannelConnector { tx: tx })
+}
+
+/// Accepts each `ChannelTransport` opened by a `ChannelConnector`.
+pub struct ChannelListener {
+    rx: Receiver<ChannelTransport>,
+}
+
+impl Listener for ChannelListener {
+    fn accept(&mut self) -> Result<Box<Transport>, Error> {
+        Ok(Box::new(self.rx.recv()?))
+    }
+}
+
+/// Opens new `ChannelTransport`s to a `ChannelListener`.
+#[derive(Clone)]
+pub struct ChannelConnector {
+    tx: Sender<ChannelTransport>,
+}
+
+impl ChannelConnector {
+    /// Open a new transport to the listener.
+    pub fn connect(&self) -> Result<ChannelTransport, Error> {
+        let (to_listener, from_connector) = mpsc::channel();
+        let (to_connector, from_listener) = mpsc::channel();
+        self.tx.send(ChannelTransport::new(to_connector, from_connector))
+            .map_err(|_| Error::Socket("channel listener closed".into()))?;
+        Ok(ChannelTransport::new(to_listener, from_listener))
+    }
+}
+
+/// One end of an in-process transport.
+pub struct ChannelTransport {
+    tx:      Sender<Vec<u8>>,
+    reader:  Arc<Mutex<ChannelReader>>,
+    timeout: Arc<Mutex<Option<Duration>>>,
+}
+
+struct ChannelReader {
+    rx:  Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelTransport {
+    fn new(tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
+        ChannelTransport {
+            tx:      tx,
+            reader:  Arc::new(Mutex::new(ChannelReader { rx: rx, buf: Vec::new(), pos: 0 })),
+            timeout: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn try_clone(&self) -> Result<Box<Transport>, Error> {
+        Ok(Box::new(ChannelTransport {
+            tx:      self.tx.clone(),
+            reader:  Arc::clone(&self.reader),
+            timeout: Arc::clone(&self.timeout),
+        }))
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        *self.timeout.lock()? = Some(timeout);
+        Ok(())
+    }
+}
+
+impl Read for ChannelTransport {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() { return Ok(0) }
+        let timeout = *self.timeout.lock().unwrap();
+        let mut reader = self.reader.lock().unwrap();
+        if reader.pos == reader.buf.len() {
+            let received = match timeout {
+                Some(timeout) => reader.rx.recv_timeout(timeout),
+                None => reader.rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            match received {
+                Ok(data) => { reader.buf = data; reader.pos = 0; }
+                Err(RecvTimeoutError::Timeout) => return Err(io::Error::new(ErrorKind::TimedOut, "channel read timed out")),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0)
+            }
+        }
+        let len = cmp::min(out.len(), reader.buf.len() - reader.pos);
+        out[..len].copy_from_slice(&reader.buf[reader.pos..reader.pos+len]);
+        reader.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for ChannelTransport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() { return Ok(0) }
+        self.tx.send(data.to_vec()).map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "channel closed"))?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/// The pair of CAN identifiers addressing one secondary over ISO-TP. Identifiers
+/// above 0x7ff are sent as 29-bit extended identifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IsoTpAddr {
+    /// The CAN identifier of messages from the primary to the secondary.
+    pub request_id:  u32,
+    /// The CAN identifier of messages from the secondary to the primary.
+    pub response_id: u32,
+}
+
+/// Offers an ISO-TP transport for each configured secondary address, offering
+/// the address again once the previous transport for it is dropped.
+pub struct IsoTpListener {
+    interface: String,
+    addrs:     Vec<IsoTpAddr>,
+    in_use:    Arc<Mutex<HashSet<IsoTpAddr>>>,
+    next:      usize,
+}
+
+impl IsoTpListener {
+    pub fn new(interface: String, addrs: Vec<IsoTpAddr>) -> Self {
+        IsoTpListener { interface: interface, addrs: addrs, in_use: Arc::new(Mutex::new(HashSet::new())), next: 0 }
+    }
+}
+
+impl Listener for IsoTpListener {
+    fn accept(&mut self) -> Result<Box<Transport>, Error> {
+        loop {
+            let free = {
+                let in_use = self.in_use.lock()?;
+                (0..self.addrs.len())
+                    .map(|n| (self.next + n) % self.addrs.len())
+                    .find(|&n| !in_use.contains(&self.addrs[n]))
+            };
+            let index = match free {
+                Some(index) => index,
+                None => { thread::sleep(Duration::from_millis(500)); continue }
+            };
+            self.next = index + 1;
+
+            let addr = self.addrs[index];
+            let fd = isotp_socket(&self.interface, addr.response_id, addr.request_id)?;
+            self.in_use.lock()?.insert(addr);
+            let socket = IsoTpSocket { fd: fd, release: Some((addr, Arc::clone(&self.in_use))) };
+            return Ok(Box::new(IsoTpTransport::new(socket)));
+        }
+    }
+}
+
+/// An ISO-TP transport on a SocketCAN interface.
+pub struct IsoTpTransport {
+    socket: Arc<IsoTpSocket>,
+    buf:    Vec<u8>,
+    pos:    usize,
+}
+
+struct IsoTpSocket {
+    fd:      RawFd,
+    release: Option<(IsoTpAddr, Arc<Mutex<HashSet<IsoTpAddr>>>)>,
+}
+
+impl Drop for IsoTpSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+        if let Some((addr, ref in_use)) = self.release {
+            in_use.lock().unwrap().remove(&addr);
+        }
+    }
+}
+
+/// The `sockaddr_can` layout for ISO-TP, which ends after the `tp` addresses.
+#[repr(C)]
+struct SockAddrCan {
+    can_family:  sa_family_t,
+    can_ifindex: c_int,
+    rx_id:       u32,
+    tx_id:       u32,
+}
+
+impl IsoTpTransport {
+    /// Connect a secondary to the primary at the given address.
+    pub fn connect(interface: &str, addr: IsoTpAddr) -> Result<Self, Error> {
+        let fd = isotp_socket(interface, addr.request_id, addr.response_id)?;
+        Ok(IsoTpTransport::new(IsoTpSocket { fd: fd, release: None }))
+    }
+
+    fn new(socket: IsoTpSocket) -> Self {
+        IsoTpTransport { socket: Arc::new(socket), buf: Vec::new(), pos: 0 }
+    }
+}
+
+impl Transport for IsoTpTransport {
+    fn try_clone(&self) -> Result<Box<Transport>, Error> {
+        Ok(Box::new(IsoTpTransport { socket: Arc::clone(&self.socket), buf: Vec::new(), pos: 0 }))
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        let tv = timeval {
+            tv_sec:  timeout.as_secs() as libc::time_t,
+            tv_usec: (timeout.subsec_nanos() / 1000) as libc::suseconds_t,
+        };
+        for opt in &[libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+            let tv_ptr = &tv as *const timeval as *const c_void;
+            if unsafe { libc::setsockopt(self.socket.fd, libc::SOL_SOCKET, *opt, tv_ptr, mem::size_of::<timeval>() as socklen_t) } < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for IsoTpTransport {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() { return Ok(0) }
+        if self.pos == self.buf.len() {
+            let mut pdu = vec![0; ISOTP_MAX_PDU];
+            let len = unsafe { libc::read(self.socket.fd, pdu.as_mut_ptr() as *mut c_void, pdu.len()) };
+            if len < 0 { return Err(io::Error::last_os_error()) }
+            pdu.truncate(len as usize);
+            self.buf = pdu;
+            self.pos = 0;
+        }
+        let len = cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..len].copy_from_slice(&self.buf[self.pos..self.pos+len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for IsoTpTransport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() { return Ok(0) }
+        let len = cmp::min(data.len(), ISOTP_MAX_PDU);
+        let written = unsafe { libc::write(self.socket.fd, data.as_ptr() as *const c_void, len) };
+        if written < 0 { Err(io::Error::last_os_error()) } else { Ok(written as usize) }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Open an ISO-TP socket on a CAN interface, receiving from `rx_id` and sending to `tx_id`.
+fn isotp_socket(interface: &str, rx_id: u32, tx_id: u32) -> Result<RawFd, Error> {
+    let name = CString::new(interface).map_err(|_| Error::Socket(format!("invalid CAN interface: {}", interface)))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(Error::Socket(format!("CAN interface {}: {}", interface, io::Error::last_os_error())));
+    }
+
+    let fd = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_DGRAM, CAN_ISOTP) };
+    if fd < 0 { return Err(io::Error::last_os_error().into()) }
+    let addr = SockAddrCan {
+        can_family:  libc::AF_CAN as sa_family_t,
+        can_ifindex: index as c_int,
+        rx_id:       can_id(rx_id),
+        tx_id:       can_id(tx_id),
+    };
+    let addr_ptr = &addr as *const SockAddrCan as *const sockaddr;
+    if unsafe { libc::bind(fd, addr_ptr, mem::size_of::<SockAddrCan>() as socklen_t) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd); }
+        return Err(err.into());
+    }
+    Ok(fd)
+}
+
+fn can_id(id: u32) -> u32 {
+    if id > CAN_SFF_MASK { id | CAN_EFF_FLAG } else { id }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn round_trip(mut client: Box<Transport>, mut server: Box<Transport>) {
+        let data = (0..10000).map(|n| n as u8).collect::<Vec<_>>();
+        let mut writer = client.try_clone().expect("clone");
+        let sent = data.clone();
+        let handle = thread::spawn(move || writer.write_all(&sent).expect("write"));
+        let mut received = vec![0; data.len()];
+        server.read_exact(&mut received).expect("read");
+        handle.join().expect("join");
+        assert_eq!(received, data);
+
+        server.write_all(b"reply").expect("reply");
+        let mut reply = [0; 5];
+        client.read_exact(&mut reply).expect("read reply");
+        assert_eq!(&reply, b"reply");
+    }
+
+    #[test]
+    fn test_channel_transport() {
+        let (mut listener, connector) = channel();
+        let client = connector.connect().expect("connect");
+        let server = listener.accept().expect("accept");
+        round_trip(Box::new(client), server);
+
+        let client = connector.connect().expect("connect");
+        client.set_timeout(Duration::from_millis(10)).expect("timeout");
+        let mut client: Box<Transport> = Box::new(client);
+        match client.read(&mut [0; 1]) {
+            Err(ref err) if err.kind() == ErrorKind::TimedOut => (),
+            other => panic!("expected timeout, got {:?}", other)
+        }
+        drop(listener);
+        assert!(connector.connect().is_err());
+        assert_eq!(client.read(&mut [0; 1]).expect("read"), 0);
+    }
+
+    #[test]
+    fn test_unix_transport() {
+        let path = format!("/tmp/sota-transport-{}.socket", ::time::precise_time_ns());
+        let mut listener = UnixListener::bind(&path).expect("bind");
+        let client = UnixStream::connect(&path).expect("connect");
+        let server = Listener::accept(&mut listener).expect("accept");
+        round_trip(Box::new(client), server);
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    /// Needs a virtual CAN interface with ISO-TP support:
+    /// `modprobe can-isotp vcan && ip link add dev vcan0 type vcan && ip link set up vcan0`
+    #[test]
+    #[ignore]
+    fn test_isotp_transport() {
+        let addr = IsoTpAddr { request_id: 0x7e0, response_id: 0x7e8 };
+        let mut listener = IsoTpListener::new("vcan0".into(), vec![addr]);
+        let server = listener.accept().expect("accept");
+        let client = IsoTpTransport::connect("vcan0", addr).expect("connect");
+        round_trip(Box::new(client), server);
+    }
+}
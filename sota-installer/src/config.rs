@@ -1,10 +1,14 @@
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
 use std::str::FromStr;
 use std::time::Duration;
 use toml;
 
 use installer::{Installer, InstallType};
-use sota::atomic::{Secondary, TcpClient};
-use sota::datatype::{Error, PrivateKey, SignatureType, SocketAddrV4, Util};
+use verify::FullVerifier;
+use sota::atomic::{BusClient, BusKeys, Secondary};
+use sota::datatype::{AttackLog, Error, PrivateKey, SignatureType, SocketAddrV4, Util};
+use sota::transport::{IsoTpAddr, IsoTpTransport, Transport, TransportType};
 
 
 pub struct App {
@@ -15,18 +19,22 @@ pub struct App {
 
 impl App {
     pub fn to_secondary(&self) -> Result<Secondary, Error> {
-        let primary = if let Some(ref addr) = self.config.primary {
-            addr.clone()
-        } else {
-            "127.0.0.1:2310".parse::<SocketAddrV4>()?
-        };
-        let client = TcpClient::new(self.config.serial.clone(), *primary)?;
-
         let sig_type = if let Some(sig_type) = self.config.signature_type {
             sig_type
         } else {
             SignatureType::RsaSsaPss
         };
+        let private_key = PrivateKey::from_der(sig_type.private_key(&Util::read_file(&self.config.private_key_path)?)?, sig_type)?;
+
+        let primary_sig_type = self.config.primary_signature_type.unwrap_or(sig_type);
+        let primary_key = primary_sig_type.public_key(&Util::read_file(&self.config.primary_public_key_path)?)?;
+        let keys = BusKeys {
+            serial:      self.config.serial.clone(),
+            private_key: private_key.clone(),
+            sig_type:    sig_type,
+            peer_keys:   hashmap!{ self.config.primary_serial.clone() => primary_key },
+        };
+        let client = BusClient::new(&keys, self.connect()?)?;
 
         let image_dir = if let Some(ref image_dir) = self.config.image_dir {
             image_dir.clone()
@@ -38,18 +46,45 @@ impl App {
             install_type: self.install_type.clone(),
 
             serial: self.config.serial.clone(),
-            private_key: PrivateKey {
-                keyid: "1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".into(),
-                der_key: Util::read_file(&self.config.private_key_path)?
-            },
+            private_key: private_key,
             sig_type: sig_type,
+            attacks: AttackLog::load(&format!("{}/attacks.json", image_dir))?,
+            verifier: FullVerifier::load(self.config.serial.clone(), self.config.hardware_id.clone(), self.config.metadata_path.clone())?,
             image_dir: image_dir,
             filepath: None,
             meta: None,
+            reported: None,
         };
 
         let timeout = Duration::from_secs(self.config.timeout.unwrap_or(300));
-        Ok(Secondary::new(client, Box::new(step), timeout, None))
+        Secondary::resume(client, Box::new(step), timeout, self.config.recover_path.clone())
+    }
+
+    fn connect(&self) -> Result<Box<Transport>, Error> {
+        match self.config.transport.unwrap_or(TransportType::Tcp) {
+            TransportType::Tcp => {
+                let primary = if let Some(ref addr) = self.config.primary {
+                    addr.clone()
+                } else {
+                    "127.0.0.1:2310".parse::<SocketAddrV4>()?
+                };
+                Ok(Box::new(TcpStream::connect(*primary)?))
+            }
+
+            TransportType::Unix => {
+                let socket = self.config.socket.as_ref().map_or("/tmp/sota-atomic.socket", |socket| socket);
+                Ok(Box::new(UnixStream::connect(socket)?))
+            }
+
+            TransportType::IsoTp => {
+                let addr = match (self.config.isotp_request_id, self.config.isotp_response_id) {
+                    (Some(request_id), Some(response_id)) => IsoTpAddr { request_id: request_id, response_id: response_id },
+                    _ => return Err(Error::Config("isotp transport needs isotp_request_id and isotp_response_id".into()))
+                };
+                let interface = self.config.can_interface.as_ref().map_or("can0", |interface| interface);
+                Ok(Box::new(IsoTpTransport::connect(interface, addr)?))
+            }
+        }
     }
 }
 
@@ -57,12 +92,24 @@ impl App {
 #[derive(Deserialize)]
 pub struct Config {
     pub serial: String,
+    pub hardware_id: String,
     pub private_key_path: String,
     pub signature_type: Option<SignatureType>,
+    pub metadata_path: String,
+    pub primary_serial: String,
+    pub primary_public_key_path: String,
+    pub primary_signature_type: Option<SignatureType>,
 
     pub timeout: Option<u64>,
     pub primary: Option<SocketAddrV4>,
     pub image_dir: Option<String>,
+    pub recover_path: Option<String>,
+
+    pub transport: Option<TransportType>,
+    pub socket: Option<String>,
+    pub can_interface: Option<String>,
+    pub isotp_request_id: Option<u32>,
+    pub isotp_response_id: Option<u32>,
 }
 
 impl FromStr for Config {
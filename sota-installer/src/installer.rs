@@ -4,8 +4,11 @@ use std::path::Path;
 
 use sota::atomic::{Payload, State, Step, StepData};
 use sota::images::{ImageMeta, ImageWriter};
-use sota::datatype::{EcuCustom, EcuVersion, Error, InstallOutcome, PrivateKey,
+use sota::datatype::{AttackLog, EcuCustom, EcuVersion, Error, InstallOutcome, PrivateKey,
                      SignatureType, TufImage, TufMeta};
+use sota::uptane::{self, DirectorMetadata};
+
+use verify::FullVerifier;
 
 
 #[derive(PartialEq, Clone, Debug)]
@@ -21,10 +24,14 @@ pub struct Installer {
     pub install_type: InstallType,
     pub private_key: PrivateKey,
     pub sig_type: SignatureType,
+    pub attacks: AttackLog,
+    pub verifier: FullVerifier,
 
     pub image_dir: String,
     pub filepath: Option<String>,
     pub meta: Option<ImageMeta>,
+    /// The signature of the last report and the number of attacks it contained.
+    pub reported: Option<(String, usize)>,
 }
 
 impl Step for Installer {
@@ -32,13 +39,25 @@ impl Step for Installer {
         match self.install_type {
             InstallType::Overwrite { ref output_dir } => {
                 match state {
-                    State::Idle   |
-                    State::Start  |
-                    State::Verify => Ok(None),
+                    State::Idle  |
+                    State::Start => Ok(None),
+
+                    State::Verify => {
+                        if let Some(Payload::UptaneMetadata(bytes)) = payload {
+                            let metadata: DirectorMetadata = json::from_slice(&bytes)?;
+                            self.acknowledge(metadata.accepted_report.as_ref())?;
+                            let result = self.verifier.verify(&metadata);
+                            detect(&self.serial, &mut self.attacks, result).map(|_| None)
+                        } else {
+                            Err(Error::UptaneTargets(format!("unexpected verify payload data: {:?}", payload)))
+                        }
+                    }
 
                     State::Fetch => {
                         if let Some(Payload::ImageMeta(bytes)) = payload {
                             let meta: ImageMeta = json::from_slice(&bytes)?;
+                            let result = self.verifier.verify_image(&meta);
+                            detect(&self.serial, &mut self.attacks, result)?;
                             self.meta = Some(meta.clone());
                             self.filepath = Some(meta.image_name.clone());
                             Ok(Some(StepData::ImageWriter(ImageWriter::new(meta, self.image_dir.clone()))))
@@ -51,23 +70,55 @@ impl Step for Installer {
                         let name = self.filepath.as_ref().expect("filepath");
                         let from = format!("{}/{}", self.image_dir, name);
                         let to = format!("{}/{}", output_dir, name);
+                        let previous = format!("{}/{}.previous", self.image_dir, name);
                         if let Some(parent) = Path::new(&to).parent() {
                             fs::create_dir_all(parent)?;
                         }
+                        if Path::new(&to).exists() {
+                            fs::copy(&to, &previous)?;
+                        } else if Path::new(&previous).exists() {
+                            fs::remove_file(&previous)?;
+                        }
                         fs::copy(&from, &to)?;
                         fs::remove_file(&from)?;
                         self.step_report(InstallOutcome::ok())
                     }
 
-                    State::Abort => self.step_report(InstallOutcome::error("aborted".into()))
+                    State::Abort => self.step_report(InstallOutcome::error(uptane::abort_reason(payload))),
+
+                    State::Rollback => {
+                        if let Some(Payload::ImageMeta(bytes)) = payload {
+                            let meta: ImageMeta = json::from_slice(&bytes)?;
+                            let to = format!("{}/{}", output_dir, meta.image_name);
+                            let previous = format!("{}/{}.previous", self.image_dir, meta.image_name);
+                            if Path::new(&previous).exists() {
+                                fs::copy(&previous, &to)?;
+                                fs::remove_file(&previous)?;
+                            } else if Path::new(&to).exists() {
+                                fs::remove_file(&to)?;
+                            }
+                            self.filepath = Some(meta.image_name.clone());
+                            self.meta = Some(meta);
+                            self.step_report(InstallOutcome::error("rolled back".into()))
+                        } else {
+                            Err(Error::Image(format!("unexpected rollback payload data: {:?}", payload)))
+                        }
+                    }
                 }
             },
         }
     }
+
+    fn attack_detected(&mut self, attack: String) {
+        let serial = self.serial.clone();
+        if let Err(err) = self.attacks.record(&serial, attack) {
+            error!("Couldn't record detected attack: {}", err);
+        }
+    }
 }
 
 impl Installer {
-    fn step_report(&self, outcome: InstallOutcome) -> Result<Option<StepData>, Error> {
+    fn step_report(&mut self, outcome: InstallOutcome) -> Result<Option<StepData>, Error> {
         let (len, sha) = if let Some(ref meta) = self.meta {
             (meta.image_size, meta.sha256sum.clone())
         } else {
@@ -78,6 +129,7 @@ impl Installer {
             fileinfo: TufMeta {
                 length: len,
                 hashes: hashmap!{ "sha256".into() => sha },
+                version: None,
                 custom: None,
             }
         };
@@ -85,17 +137,113 @@ impl Installer {
         let custom = EcuCustom::from_result(outcome.into_result(self.serial.clone()));
         let version = self.to_version(image, Some(custom));
         let report = self.private_key.sign_data(json::to_value(version)?, self.sig_type)?;
+        self.reported = report.signatures.first().map(|sig| (sig.sig.clone(), self.attacks.count(&self.serial)));
         Ok(Some(StepData::TufReport(report)))
     }
 
+    /// Forget the attacks in the last report once the primary confirms that
+    /// the Director accepted it.
+    fn acknowledge(&mut self, accepted: Option<&String>) -> Result<(), Error> {
+        let count = match (accepted, self.reported.as_ref()) {
+            (Some(accepted), Some(&(ref sig, count))) if accepted == sig => count,
+            _ => return Ok(())
+        };
+        let serial = self.serial.clone();
+        self.attacks.clear_reported(&serial, count)?;
+        self.reported = None;
+        Ok(())
+    }
+
     fn to_version(&self, image: TufImage, custom: Option<EcuCustom>) -> EcuVersion {
-        EcuVersion {
-            attacks_detected: "".into(),
-            custom: custom,
-            ecu_serial: self.serial.clone(),
-            installed_image: image,
-            previous_timeserver_time: "1970-01-01T00:00:00Z".into(),
-            timeserver_time: "1970-01-01T00:00:00Z".into(),
+        let mut version = EcuVersion::from(self.serial.clone(), image, custom);
+        version.attacks_detected = self.attacks.report(&self.serial);
+        version
+    }
+}
+
+/// Record any verification failure that indicates an attack on this ECU.
+fn detect<T>(serial: &str, attacks: &mut AttackLog, result: Result<T, Error>) -> Result<T, Error> {
+    if let Err(ref err) = result {
+        if uptane::is_attack(err) {
+            if let Err(err) = attacks.record(serial, err.to_string()) {
+                error!("Couldn't record detected attack: {}", err);
+            }
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sota::datatype::Util;
+    use sota::pacman::test::TestDir;
+
+
+    fn new_installer(dir: &TestDir) -> Installer {
+        fs::copy("../sota-client/tests/uptane_basic/director/root.json", format!("{}/root.json", dir.0)).expect("copy root");
+        let pem = Util::read_file("../sota-client/tests/keys/ed25519.pem").expect("ed25519.pem");
+        let der_key = SignatureType::Ed25519.private_key(&pem).expect("private key");
+        let keyid = SignatureType::Ed25519.public_from_private(&der_key).and_then(|key| key.key_id()).expect("key id");
+        Installer {
+            serial: "secondary".into(),
+            install_type: InstallType::Overwrite { output_dir: format!("{}/output", dir.0) },
+            private_key: PrivateKey { keyid: keyid, der_key: der_key },
+            sig_type: SignatureType::Ed25519,
+            attacks: AttackLog::load(&format!("{}/attacks.json", dir.0)).expect("attacks"),
+            verifier: FullVerifier::load("secondary".into(), "hardware".into(), dir.0.clone()).expect("verifier"),
+            image_dir: format!("{}/images", dir.0),
+            filepath: None,
+            meta: None,
+            reported: None,
         }
     }
+
+    #[test]
+    fn test_attacks_cleared_when_accepted() {
+        let dir = TestDir::new("sota-installer-accepted");
+        let mut installer = new_installer(&dir);
+        installer.attack_detected("first".into());
+        let report = match installer.step_report(InstallOutcome::ok()).expect("report") {
+            Some(StepData::TufReport(report)) => report,
+            _ => panic!("expected a signed report")
+        };
+        assert_eq!(report.signed["attacks_detected"], json::Value::from("first"));
+
+        installer.attack_detected("second".into());
+        installer.acknowledge(None).expect("no ack");
+        installer.acknowledge(Some(&"another report".into())).expect("other ack");
+        assert_eq!(installer.attacks.report("secondary"), "first; second");
+
+        installer.acknowledge(Some(&report.signatures[0].sig)).expect("ack");
+        assert_eq!(installer.attacks.report("secondary"), "second");
+        assert_eq!(AttackLog::load(&format!("{}/attacks.json", dir.0)).unwrap().report("secondary"), "second");
+    }
+
+    #[test]
+    fn test_rollback_restores_previous() {
+        let dir = TestDir::new("sota-installer-rollback");
+        let mut installer = new_installer(&dir);
+        let (image, output) = (format!("{}/images/image.bin", dir.0), format!("{}/output/image.bin", dir.0));
+        let previous = format!("{}/images/image.bin.previous", dir.0);
+        fs::create_dir_all(format!("{}/images", dir.0)).expect("image dir");
+        installer.filepath = Some("image.bin".into());
+
+        Util::write_file(&image, b"old").expect("write old");
+        installer.step(State::Commit, None).expect("commit old");
+        assert!(! Path::new(&previous).exists());
+        Util::write_file(&image, b"new").expect("write new");
+        installer.step(State::Commit, None).expect("commit new");
+        assert_eq!(Util::read_file(&output).expect("output"), b"new");
+        assert_eq!(Util::read_file(&previous).expect("previous"), b"old");
+
+        let meta = ImageMeta::new("image.bin".into(), 3, 1, "".into());
+        let payload = Payload::ImageMeta(json::to_vec(&meta).expect("meta").into());
+        installer.step(State::Rollback, Some(payload.clone())).expect("rollback");
+        assert_eq!(Util::read_file(&output).expect("output"), b"old");
+        assert!(! Path::new(&previous).exists());
+        installer.step(State::Rollback, Some(payload)).expect("rollback without previous");
+        assert!(! Path::new(&output).exists());
+    }
 }
@@ -1,4 +1,5 @@
 #[macro_use] extern crate clap;
+#[cfg(test)] extern crate crypto;
 extern crate env_logger;
 #[macro_use] extern crate log;
 #[macro_use] extern crate maplit;
@@ -11,6 +12,7 @@ extern crate uuid;
 
 mod config;
 mod installer;
+mod verify;
 
 use clap::AppSettings;
 use env_logger::LogBuilder;
@@ -36,10 +38,16 @@ fn start() -> Result<(), Error> {
 
     loop {
         info!("Starting a new listener...");
-        let mut secondary = app.to_secondary()?;
-        match secondary.listen() {
-            Ok(()) => info!("Listener complete."),
-            Err(err) => error!("Listener error: {}", err)
+        match app.to_secondary() {
+            Ok(mut secondary) => loop {
+                match secondary.listen() {
+                    Ok(()) => info!("Listener complete."),
+                    Err(err) => { error!("Listener error: {}", err); break }
+                }
+                if oneshot { break }
+            },
+            Err(ref err) if ! oneshot => error!("Unable to start listener: {}", err),
+            Err(err) => return Err(err)
         }
         if oneshot { break } else { thread::sleep(Duration::from_secs(1)) }
     }
@@ -0,0 +1,340 @@
+use json;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use sota::datatype::{Error, RoleData, RoleName, TufMeta, TufSigned, Util};
+use sota::images::ImageMeta;
+use sota::uptane::{self, DirectorMetadata, Verified, Verifier};
+
+
+/// Keeps this ECU's own trusted Director root and role metadata so that the
+/// metadata delivered by the primary is fully verified rather than trusted.
+pub struct FullVerifier {
+    serial:        String,
+    hardware_id:   String,
+    metadata_path: String,
+    verifier:      Verifier,
+    targets:       Option<HashMap<String, TufMeta>>,
+}
+
+impl FullVerifier {
+    /// Load the trusted Director `root.json` and the versions of any previously
+    /// verified roles from the metadata directory.
+    pub fn load(serial: String, hardware_id: String, metadata_path: String) -> Result<Self, Error> {
+        let root = read_data(&format!("{}/root.json", metadata_path))?;
+        let mut verifier = Verifier::from_root(&root)?;
+        for role in &[RoleName::Targets, RoleName::Snapshot, RoleName::Timestamp] {
+            let path = format!("{}/{}.json", metadata_path, role);
+            if Path::new(&path).exists() {
+                verifier.set_version(role.clone(), read_data(&path)?.version);
+            }
+        }
+
+        Ok(FullVerifier {
+            serial:        serial,
+            hardware_id:   hardware_id,
+            metadata_path: metadata_path,
+            verifier:      verifier,
+            targets:       None,
+        })
+    }
+
+    /// Verify the delivered chain from any newer roots through to the targets,
+    /// then keep the targets assigned to this ECU for checking images. Nothing
+    /// is trusted or persisted unless the whole chain verifies, and the role
+    /// files a key rotation invalidated are removed before the new root is
+    /// written so a reload never restores their old versions.
+    pub fn verify(&mut self, metadata: &DirectorMetadata) -> Result<(), Error> {
+        let mut verifier = self.verifier.clone();
+        let mut roots = Vec::new();
+        for json in &metadata.roots {
+            let signed = uptane::parse_signed(json.as_bytes())?;
+            let version = json::from_value::<RoleData>(signed.signed.clone())?.version;
+            if version <= verifier.version(RoleName::Root) { continue }
+            let root = verifier.rotate_root(signed)?;
+            info!("Director root rotated from version {} to {}", root.old_ver, root.new_ver);
+            roots.push((root.new_ver, json));
+        }
+        if verifier.root_expired() {
+            return Err(Error::UptaneExpired);
+        }
+
+        let timestamp = verify_role(&mut verifier, RoleName::Timestamp, &metadata.timestamp, None)?;
+        let snapshot = verify_chained(&mut verifier, &timestamp, RoleName::Snapshot, &metadata.snapshot)?;
+        let targets = verify_chained(&mut verifier, &snapshot, RoleName::Targets, &metadata.targets)?;
+
+        let mut assigned = HashMap::new();
+        for (refname, meta) in targets.data.targets.unwrap_or_default() {
+            let hardware_id = match meta.custom {
+                Some(ref custom) if custom.ecuIdentifier.as_ref() == Some(&self.serial) => custom.hardwareIdentifier.clone(),
+                _ => continue
+            };
+            if hardware_id.as_ref() != Some(&self.hardware_id) {
+                return Err(Error::UptaneHardwareId(format!("refname {} is for {}, ECU {} is {}", refname,
+                    hardware_id.as_ref().map_or("unknown hardware", |id| id), self.serial, self.hardware_id)));
+            }
+            assigned.insert(refname, meta);
+        }
+        if assigned.is_empty() {
+            return Err(Error::UptaneTargets(format!("no targets assigned to {}", self.serial)));
+        }
+
+        for role in &[RoleName::Snapshot, RoleName::Timestamp] {
+            let path = format!("{}/{}.json", self.metadata_path, role);
+            if verifier.version(role.clone()) < self.verifier.version(role.clone()) && Path::new(&path).exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        for (version, json) in roots {
+            Util::write_file(&format!("{}/root.json", self.metadata_path), json.as_bytes())?;
+            Util::write_file(&format!("{}/{}.root.json", self.metadata_path, version), json.as_bytes())?;
+        }
+        Util::write_file(&format!("{}/timestamp.json", self.metadata_path), metadata.timestamp.as_bytes())?;
+        Util::write_file(&format!("{}/snapshot.json", self.metadata_path), metadata.snapshot.as_bytes())?;
+        Util::write_file(&format!("{}/targets.json", self.metadata_path), metadata.targets.as_bytes())?;
+        self.verifier = verifier;
+        self.targets = Some(assigned);
+        Ok(())
+    }
+
+    /// Check that an image offered by the primary matches a verified target.
+    pub fn verify_image(&self, image: &ImageMeta) -> Result<(), Error> {
+        let targets = self.targets.as_ref().ok_or_else(|| Error::UptaneTargets("no verified metadata".into()))?;
+        let expected = targets.get(&image.image_name)
+            .ok_or_else(|| Error::UptaneTargets(format!("{} is not assigned to {}", image.image_name, self.serial)))?;
+        let offered = TufMeta {
+            length:  image.image_size,
+            hashes:  hashmap!{ "sha256".into() => image.sha256sum.clone() },
+            version: None,
+            custom:  None,
+        };
+        offered.verify_meta(&image.image_name, expected)
+    }
+}
+
+fn verify_role(verifier: &mut Verifier, role: RoleName, json: &str, version: Option<u64>) -> Result<Verified, Error> {
+    let signed = uptane::parse_signed(json.as_bytes())?;
+    verifier.verify_signed_version(role, signed, version)
+}
+
+fn verify_chained(verifier: &mut Verifier, parent: &Verified, role: RoleName, json: &str) -> Result<Verified, Error> {
+    let name = format!("{}.json", role);
+    let meta = parent.data.meta.as_ref()
+        .and_then(|meta| meta.get(&name))
+        .ok_or_else(|| Error::UptaneMissingMeta(format!("{} in {}", name, parent.role)))?;
+    meta.verify(&name, json.as_bytes())?;
+    verify_role(verifier, role, json, meta.version)
+}
+
+fn read_data(path: &str) -> Result<RoleData, Error> {
+    let signed = json::from_slice::<TufSigned>(&Util::read_file(path)?)?;
+    Ok(json::from_value(signed.signed)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::digest::Digest;
+    use crypto::ed25519;
+    use crypto::sha2::Sha256;
+
+    use sota::datatype::{PrivateKey, RoleMeta, SignatureType};
+    use sota::pacman::test::TestDir;
+
+
+    fn key(seed: u8) -> PrivateKey {
+        let (private, _) = ed25519::keypair(&[seed; 32]);
+        let keyid = SignatureType::Ed25519.public_from_private(&private).and_then(|key| key.key_id()).expect("key id");
+        PrivateKey { keyid: keyid, der_key: private.to_vec() }
+    }
+
+    fn role_data(role: &str, version: u64) -> RoleData {
+        json::from_str(&format!(r#"{{"_type": "{}", "version": {}, "expires": "2037-01-01T00:00:00Z"}}"#, role, version)).expect("role data")
+    }
+
+    fn sign(data: &RoleData, seeds: &[u8]) -> String {
+        let value = json::to_value(data).expect("to_value");
+        let mut signed = key(seeds[0]).sign_data(value.clone(), SignatureType::Ed25519).expect("sign");
+        for seed in &seeds[1..] {
+            signed.signatures.extend(key(*seed).sign_data(value.clone(), SignatureType::Ed25519).expect("sign").signatures);
+        }
+        json::to_string(&signed).expect("to_string")
+    }
+
+    /// Root metadata that delegates every role to a single key.
+    fn root(version: u64, seed: u8) -> RoleData {
+        let key = key(seed);
+        let meta = RoleMeta { keyids: hashset!{ key.keyid.clone() }, threshold: 1, version: 0 };
+        let mut data = role_data("Root", version);
+        data.keys = Some(hashmap!{ key.keyid.clone() => SignatureType::Ed25519.public_from_private(&key.der_key).expect("public key") });
+        data.roles = Some(hashmap!{
+            RoleName::Root      => meta.clone(),
+            RoleName::Targets   => meta.clone(),
+            RoleName::Snapshot  => meta.clone(),
+            RoleName::Timestamp => meta,
+        });
+        data
+    }
+
+    fn file_meta(data: &[u8], version: Option<u64>) -> TufMeta {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+        TufMeta { length: data.len() as u64, hashes: hashmap!{ "sha256".into() => hasher.result_str() }, version: version, custom: None }
+    }
+
+    fn target(serial: &str, hardware: &str, image: &[u8]) -> TufMeta {
+        let mut meta = file_meta(image, None);
+        meta.custom = Some(json::from_str(&format!(r#"{{"ecuIdentifier": "{}", "hardwareIdentifier": "{}"}}"#, serial, hardware)).expect("custom"));
+        meta
+    }
+
+    /// Sign a Director chain with the given timestamp, snapshot and targets versions.
+    fn director(seed: u8, versions: (u64, u64, u64), targets: HashMap<String, TufMeta>) -> DirectorMetadata {
+        let (timestamp_ver, snapshot_ver, targets_ver) = versions;
+        let mut data = role_data("Targets", targets_ver);
+        data.targets = Some(targets);
+        let targets = sign(&data, &[seed]);
+        let mut data = role_data("Snapshot", snapshot_ver);
+        data.meta = Some(hashmap!{ "targets.json".into() => file_meta(targets.as_bytes(), Some(targets_ver)) });
+        let snapshot = sign(&data, &[seed]);
+        let mut data = role_data("Timestamp", timestamp_ver);
+        data.meta = Some(hashmap!{ "snapshot.json".into() => file_meta(snapshot.as_bytes(), Some(snapshot_ver)) });
+        let timestamp = sign(&data, &[seed]);
+        DirectorMetadata { roots: Vec::new(), timestamp: timestamp, snapshot: snapshot, targets: targets, accepted_report: None }
+    }
+
+    fn new_verifier(dir: &TestDir) -> FullVerifier {
+        Util::write_file(&format!("{}/root.json", dir.0), sign(&root(1, 1), &[1]).as_bytes()).expect("write root");
+        FullVerifier::load("secondary".into(), "hardware".into(), dir.0.clone()).expect("verifier")
+    }
+
+    fn image_meta(name: &str, image: &[u8]) -> ImageMeta {
+        let meta = file_meta(image, None);
+        ImageMeta::new(name.into(), meta.length, 1, meta.hashes["sha256"].clone())
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let dir = TestDir::new("sota-installer-verify-chain");
+        let mut verifier = new_verifier(&dir);
+        let metadata = director(1, (1, 1, 1), hashmap!{ "image".into() => target("secondary", "hardware", b"image") });
+        verifier.verify(&metadata).expect("verify chain");
+        assert_eq!(Util::read_text(&format!("{}/targets.json", dir.0)).expect("targets.json"), metadata.targets);
+        verifier.verify_image(&image_meta("image", b"image")).expect("verify image");
+
+        let verifier = FullVerifier::load("secondary".into(), "hardware".into(), dir.0.clone()).expect("reload");
+        assert_eq!(verifier.verifier.version(RoleName::Targets), 1);
+    }
+
+    #[test]
+    fn test_verify_root_rotation() {
+        let dir = TestDir::new("sota-installer-verify-root");
+        let mut verifier = new_verifier(&dir);
+        let mut metadata = director(2, (1, 1, 1), hashmap!{ "image".into() => target("secondary", "hardware", b"image") });
+        match verifier.verify(&metadata) {
+            Err(Error::UptaneThreshold(_)) => (),
+            other => panic!("expected the new key to be untrusted, got {:?}", other)
+        }
+
+        let next = sign(&root(2, 2), &[1, 2]);
+        metadata.roots = vec![sign(&root(1, 1), &[1]), next.clone()];
+        verifier.verify(&metadata).expect("verify after rotation");
+        assert_eq!(verifier.verifier.version(RoleName::Root), 2);
+        assert_eq!(Util::read_text(&format!("{}/root.json", dir.0)).expect("root.json"), next);
+        assert_eq!(Util::read_text(&format!("{}/2.root.json", dir.0)).expect("2.root.json"), next);
+    }
+
+    #[test]
+    fn test_verify_rotation_reload() {
+        let dir = TestDir::new("sota-installer-verify-rotation-reload");
+        let mut verifier = new_verifier(&dir);
+        let targets = hashmap!{ "image".to_string() => target("secondary", "hardware", b"image") };
+        verifier.verify(&director(1, (5, 5, 5), targets.clone())).expect("verify chain");
+
+        let mut metadata = director(2, (1, 1, 5), targets.clone());
+        metadata.roots = vec![sign(&root(2, 2), &[1, 2])];
+        verifier.verify(&metadata).expect("verify after rotation");
+        assert_eq!(verifier.verifier.version(RoleName::Timestamp), 1);
+        assert_eq!(verifier.verifier.version(RoleName::Snapshot), 1);
+
+        let mut verifier = FullVerifier::load("secondary".into(), "hardware".into(), dir.0.clone()).expect("reload");
+        assert_eq!(verifier.verifier.version(RoleName::Root), 2);
+        assert_eq!(verifier.verifier.version(RoleName::Timestamp), 1);
+        assert_eq!(verifier.verifier.version(RoleName::Snapshot), 1);
+        assert_eq!(verifier.verifier.version(RoleName::Targets), 5);
+        verifier.verify(&director(2, (2, 2, 5), targets)).expect("verify after reload");
+    }
+
+    #[test]
+    fn test_verify_rollback() {
+        let dir = TestDir::new("sota-installer-verify-rollback");
+        let mut verifier = new_verifier(&dir);
+        let targets = hashmap!{ "image".to_string() => target("secondary", "hardware", b"image") };
+        verifier.verify(&director(1, (2, 2, 2), targets.clone())).expect("verify chain");
+
+        let mut verifier = FullVerifier::load("secondary".into(), "hardware".into(), dir.0.clone()).expect("reload");
+        match verifier.verify(&director(1, (1, 2, 2), targets.clone())) {
+            Err(Error::UptaneVersion) => (),
+            other => panic!("expected timestamp rollback to be rejected, got {:?}", other)
+        }
+        match verifier.verify(&director(1, (3, 1, 2), targets.clone())) {
+            Err(Error::UptaneVersion) => (),
+            other => panic!("expected snapshot rollback to be rejected, got {:?}", other)
+        }
+        verifier.verify(&director(1, (3, 3, 2), targets)).expect("verify newer chain");
+    }
+
+    #[test]
+    fn test_verify_other_serial() {
+        let dir = TestDir::new("sota-installer-verify-serial");
+        let mut verifier = new_verifier(&dir);
+        match verifier.verify(&director(1, (1, 1, 1), hashmap!{ "other".into() => target("other-serial", "hardware", b"other") })) {
+            Err(Error::UptaneTargets(_)) => (),
+            other => panic!("expected no assigned targets, got {:?}", other)
+        }
+
+        let targets = hashmap!{
+            "image".into() => target("secondary", "hardware", b"image"),
+            "other".into() => target("other-serial", "hardware", b"other"),
+        };
+        verifier.verify(&director(1, (1, 1, 1), targets)).expect("verify chain");
+        match verifier.verify_image(&image_meta("other", b"other")) {
+            Err(Error::UptaneTargets(_)) => (),
+            other => panic!("expected another ECU's image to be rejected, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_verify_hardware_mismatch() {
+        let dir = TestDir::new("sota-installer-verify-hardware");
+        let mut verifier = new_verifier(&dir);
+        match verifier.verify(&director(1, (2, 2, 2), hashmap!{ "image".into() => target("secondary", "other-hardware", b"image") })) {
+            Err(Error::UptaneHardwareId(_)) => (),
+            other => panic!("expected hardware mismatch, got {:?}", other)
+        }
+        for role in &["timestamp", "snapshot", "targets"] {
+            assert!(!Path::new(&format!("{}/{}.json", dir.0, role)).exists());
+        }
+        assert_eq!(verifier.verifier.version(RoleName::Timestamp), 0);
+        verifier.verify(&director(1, (1, 1, 1), hashmap!{ "image".into() => target("secondary", "hardware", b"image") }))
+            .expect("nothing trusted from the rejected chain");
+    }
+
+    #[test]
+    fn test_verify_image() {
+        let dir = TestDir::new("sota-installer-verify-image");
+        let mut verifier = new_verifier(&dir);
+        verifier.verify(&director(1, (1, 1, 1), hashmap!{ "image".into() => target("secondary", "hardware", b"image") })).expect("verify chain");
+        verifier.verify_image(&image_meta("image", b"image")).expect("verify image");
+        match verifier.verify_image(&image_meta("image", b"imagf")) {
+            Err(Error::UptaneMetaHash(_)) => (),
+            other => panic!("expected hash mismatch, got {:?}", other)
+        }
+        match verifier.verify_image(&image_meta("image", b"images")) {
+            Err(Error::UptaneMetaLength(_)) => (),
+            other => panic!("expected length mismatch, got {:?}", other)
+        }
+    }
+}
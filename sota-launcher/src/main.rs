@@ -23,7 +23,7 @@ use std::process;
 use config::*;
 use manifests::*;
 use mtu::*;
-use sota::datatype::Util;
+use sota::datatype::{TargetFormat, Util};
 
 
 fn main() {
@@ -31,6 +31,7 @@ impl Manifests {
             fileinfo: TufMeta {
                 length: 0,
                 hashes: hashmap!{ "sha256".into() => current.commit },
+                version: None,
                 custom: None,
             }
         })
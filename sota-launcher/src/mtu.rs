@@ -9,6 +9,7 @@ use std::str::FromStr;
 use uuid::Uuid;
 
 use config::*;
+use sota::datatype::TargetFormat;
 
 
 pub struct MultiTargetUpdate {
@@ -87,13 +88,6 @@ impl UpdateTargets {
 }
 
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum TargetFormat {
-    Binary,
-    Ostree,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Update {
     pub from: Option<UpdateTarget>,